@@ -0,0 +1,63 @@
+// Sends a single action to an AMI server and prints everything it sent back.
+//
+// cargo run -p ami --example action -- <host> <port> <username> <secret> <Action> [Name=Value...]
+// e.g. cargo run -p ami --example action -- 127.0.0.1 5038 admin admin QueueStatus Queue=sales
+
+use std::{env, process, time::Duration};
+use ami::{AmiClient, AuthType, ConnectOptions};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 5 {
+        println!("Usage: action <host> <port> <username> <secret> <Action> [Name=Value...]");
+        process::exit(1);
+    }
+
+    let options = ConnectOptions {
+        host: args[0].clone(),
+        port: args[1].parse().expect("Invalid port"),
+        ..ConnectOptions::default()
+    };
+
+    let mut client = match AmiClient::connect(&options) {
+        Ok(client) => client,
+        Err(e) => {
+            println!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    if let Err(e) = client.login(&args[2], &args[3], AuthType::Plaintext, None) {
+        println!("Error: {}", e);
+        process::exit(1);
+    }
+
+    let mut fields = vec![("Action", args[4].as_str())];
+    for arg in &args[5..] {
+        if let Some((name, value)) = arg.split_once('=') {
+            fields.push((name, value));
+        }
+    }
+
+    match client.call(&fields, Duration::from_secs(10)) {
+        Ok(result) => {
+            println!("{}", format_packet(&result.response));
+            for event in &result.events {
+                println!("{}", format_packet(event));
+            }
+        },
+        Err(e) => {
+            println!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+// Prints a packet the way it came over the wire.
+fn format_packet(packet: &ami::Packet) -> String {
+    let mut text = String::new();
+    for (name, value) in packet.headers.iter() {
+        text.push_str(&format!("{}: {}\n", name, value));
+    }
+    text
+}
@@ -0,0 +1,187 @@
+use std::{collections::HashMap, sync::{Arc, atomic::{AtomicU64, Ordering}, mpsc::{self, Receiver, RecvTimeoutError, Sender}}, time::Duration};
+
+use crate::message::{AmiMessage, Packet};
+
+// Everything the server sent back for one action: the Response, and for list actions (QueueStatus, CoreShowChannels...)
+// all the events that followed it up to the ...Complete event.
+#[derive(Debug, Clone)]
+pub struct ActionResult {
+    pub response: Packet,
+    pub events: Vec<Packet>,
+}
+
+impl ActionResult {
+    pub fn is_success(&self) -> bool {
+        match self.response.headers.get("Response") {
+            Some(response) => response == "Success" || response == "Follows" || response == "Goodbye",
+            None => false,
+        }
+    }
+}
+
+// The caller side of an action that was sent, resolves once its result is complete.
+// If the connection is lost before that, the handle resolves to None.
+pub struct ActionHandle {
+    action_id: String,
+    receiver: Receiver<ActionResult>,
+}
+
+impl ActionHandle {
+    pub(crate) fn new(action_id: String, receiver: Receiver<ActionResult>) -> Self {
+        ActionHandle {
+            action_id,
+            receiver,
+        }
+    }
+
+    pub fn action_id(&self) -> &str {
+        &self.action_id
+    }
+
+    // The result if it is already complete, without blocking.
+    pub fn try_result(&self) -> Option<ActionResult> {
+        self.receiver.try_recv().ok()
+    }
+
+    // Blocks until the result is complete.
+    // Only use this from another thread than the one receiving from the client, that one should use AmiClient::call.
+    pub fn wait(self) -> Option<ActionResult> {
+        self.receiver.recv().ok()
+    }
+
+    // Blocks until the result is complete or the timeout passes.
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<ActionResult> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(result) => Some(result),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+}
+
+// Generates the ActionIDs for a connection, shared with its ActionSenders so they never collide.
+#[derive(Clone)]
+pub(crate) struct ActionIds {
+    prefix: String,
+    next: Arc<AtomicU64>,
+}
+
+impl ActionIds {
+    pub(crate) fn new(prefix: &str) -> Self {
+        ActionIds {
+            prefix: prefix.to_owned(),
+            next: Arc::new(AtomicU64::new(1)),
+        }
+    }
+
+    pub(crate) fn next(&self) -> String {
+        format!("{}-{}", self.prefix, self.next.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+// An action handed over by an ActionSender, waiting to be written by the thread that owns the client.
+pub(crate) struct QueuedAction {
+    pub(crate) fields: Vec<(String, String)>,
+    pub(crate) action_id: String,
+    pub(crate) result: Sender<ActionResult>,
+}
+
+// Lets other threads send actions over a client owned by another thread.
+// The actions are written the next time the owning thread calls AmiClient::recv.
+#[derive(Clone)]
+pub struct ActionSender {
+    ids: ActionIds,
+    sender: Sender<QueuedAction>,
+}
+
+impl ActionSender {
+    pub(crate) fn new(ids: ActionIds, sender: Sender<QueuedAction>) -> Self {
+        ActionSender {
+            ids,
+            sender,
+        }
+    }
+
+    // Queues an action, an ActionID is generated unless the fields already have one.
+    // Returns None if the client is gone.
+    pub fn send(&self, fields: &[(&str, &str)]) -> Option<ActionHandle> {
+        let action_id = action_id_of(fields).unwrap_or_else(|| self.ids.next());
+        let (result, receiver) = mpsc::channel();
+
+        let action = QueuedAction {
+            fields: fields.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            action_id: action_id.clone(),
+            result,
+        };
+        self.sender.send(action).ok()?;
+
+        Some(ActionHandle::new(action_id, receiver))
+    }
+}
+
+pub(crate) fn action_id_of(fields: &[(&str, &str)]) -> Option<String> {
+    fields.iter().find(|(name, _)| name.eq_ignore_ascii_case("ActionID")).map(|(_, value)| value.to_string())
+}
+
+struct Pending {
+    result: Sender<ActionResult>,
+    response: Option<Packet>,
+    events: Vec<Packet>,
+}
+
+// Matches what the server sends to the actions waiting for a result, by ActionID.
+#[derive(Default)]
+pub(crate) struct Tracker {
+    pending: HashMap<String, Pending>,
+}
+
+impl Tracker {
+    pub(crate) fn register(&mut self, action_id: String, result: Sender<ActionResult>) {
+        self.pending.insert(action_id, Pending {
+            result,
+            response: None,
+            events: vec![],
+        });
+    }
+
+    // Takes the message if it belongs to a pending action, otherwise gives it back.
+    pub(crate) fn route(&mut self, message: AmiMessage) -> Option<AmiMessage> {
+        let action_id = match message.packet().and_then(|packet| packet.action_id()) {
+            Some(action_id) if self.pending.contains_key(action_id) => action_id.to_owned(),
+            _ => {
+                return Some(message);
+            }
+        };
+
+        let pending = self.pending.get_mut(&action_id)?;
+        let complete = match message {
+            AmiMessage::Response(packet) => {
+                // List actions announce the events that will follow with "EventList: start".
+                let is_list = packet.headers.get("EventList").map(|list| list.eq_ignore_ascii_case("start")).unwrap_or(false);
+                pending.response = Some(packet);
+                !is_list
+            },
+            AmiMessage::Event(packet) => {
+                // The list ends with an event like QueueStatusComplete, newer versions also mark it with "EventList: Complete".
+                let is_last = packet.headers.get("EventList").map(|list| list.eq_ignore_ascii_case("complete")).unwrap_or(false)
+                    || packet.event_name().map(|event| event.ends_with("Complete")).unwrap_or(false);
+                pending.events.push(packet);
+                is_last && pending.response.is_some()
+            },
+            AmiMessage::Banner(_) => false,
+        };
+
+        if complete {
+            if let Some(pending) = self.pending.remove(&action_id) {
+                if let Some(response) = pending.response {
+                    // The handle may have been dropped, nobody wants the result then.
+                    let _ = pending.result.send(ActionResult {
+                        response,
+                        events: pending.events,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+}
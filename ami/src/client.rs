@@ -0,0 +1,449 @@
+use std::{collections::VecDeque, io::{BufReader, Write}, net::{TcpStream, ToSocketAddrs}, sync::mpsc::{self, Receiver, Sender}, time::{Duration, Instant}};
+
+use crate::{AuthType, action::{self, ActionHandle, ActionIds, ActionResult, ActionSender, QueuedAction, Tracker}, error::AmiError, message::{AmiMessage, Packet, Parser}, stream::{AmiStream, TlsOptions}, version::AmiVersion};
+
+// Where and how to connect to an AMI server.
+#[derive(Debug, Clone)]
+pub struct ConnectOptions {
+    pub host: String,
+    pub port: u16,
+    // Connect with AMI over TLS (usually on port 5039) when set.
+    pub tls: Option<TlsOptions>,
+    // How long to wait for the connection, the TLS handshake, the banner and the replies during login.
+    pub timeout: Duration,
+    // How long a single read blocks, AmiClient::recv returns None after it so the caller can do other work in between.
+    pub poll_interval: Duration,
+    // Generated ActionIDs look like "<prefix>-<n>".
+    pub action_id_prefix: String,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        ConnectOptions {
+            host: String::from("127.0.0.1"),
+            port: 5038,
+            tls: None,
+            timeout: Duration::from_secs(10),
+            poll_interval: Duration::from_millis(500),
+            action_id_prefix: String::from("ami"),
+        }
+    }
+}
+
+// A connection to an AMI server.
+// Typical usage is connect, check the version, login, and then send actions and receive messages.
+// Actions sent with send, call or through an ActionSender get an ActionID, and whatever the server sends back for them
+// is routed to their ActionHandle instead of being returned by recv.
+pub struct AmiClient {
+    parser: Parser<BufReader<AmiStream>>,
+    version: AmiVersion,
+    timeout: Duration,
+    ids: ActionIds,
+    tracker: Tracker,
+    // Messages received while call was waiting for its result, recv returns them first.
+    backlog: VecDeque<AmiMessage>,
+    queue_sender: Sender<QueuedAction>,
+    queue: Receiver<QueuedAction>,
+}
+
+impl AmiClient {
+    // Opens the connection to the server and reads its banner.
+    pub fn connect(options: &ConnectOptions) -> Result<AmiClient, AmiError> {
+        let stream = open_tcp(&options.host, options.port, options.timeout)?;
+
+        // Nothing should block for longer than the timeout, including the TLS handshake.
+        stream.set_read_timeout(Some(options.timeout))?;
+        stream.set_write_timeout(Some(options.timeout))?;
+
+        let stream = match &options.tls {
+            Some(tls) => {
+                let connector = tls.connector()?;
+                match connector.connect(&options.host, stream) {
+                    Ok(stream) => AmiStream::Tls(Box::new(stream)),
+                    Err(e) => {
+                        return Err(AmiError::TlsError(e.to_string()));
+                    }
+                }
+            },
+            None => AmiStream::Plain(stream),
+        };
+
+        // From now on reads never block for long, so the caller can notice a dead connection.
+        stream.tcp().set_read_timeout(Some(options.poll_interval))?;
+
+        let mut parser = Parser::new(BufReader::new(stream));
+
+        // Lets check if the first line is an AMI banner, and which protocol version it announces.
+        let deadline = Instant::now() + options.timeout;
+        let banner = loop {
+            if let Some(AmiMessage::Banner(banner)) = parser.read_banner()? {
+                break banner;
+            }
+            if Instant::now() >= deadline {
+                return Err(AmiError::Timeout);
+            }
+        };
+
+        let version = match AmiVersion::from_banner(&banner) {
+            Some(version) => version,
+            None => {
+                return Err(AmiError::UnknownBanner(banner));
+            }
+        };
+
+        let (queue_sender, queue) = mpsc::channel();
+
+        Ok(AmiClient {
+            parser,
+            version,
+            timeout: options.timeout,
+            ids: ActionIds::new(&options.action_id_prefix),
+            tracker: Tracker::default(),
+            backlog: VecDeque::new(),
+            queue_sender,
+            queue,
+        })
+    }
+
+    // The AMI protocol version announced by the server.
+    pub fn version(&self) -> &AmiVersion {
+        &self.version
+    }
+
+    // Logs in, with the secret itself or a key derived from a challenge depending on auth.
+    // events is the event mask for this connection, e.g. "call,agent", "off" or None to keep the server default.
+    pub fn login(&mut self, username: &str, secret: &str, auth: AuthType, events: Option<&str>) -> Result<(), AmiError> {
+        let key;
+        let mut fields = match auth {
+            AuthType::Plaintext => {
+                vec![
+                    ("Action", "Login"),
+                    ("Username", username),
+                    ("Secret", secret),
+                ]
+            },
+            AuthType::Md5 => {
+                self.send_action(&[
+                    ("Action", "Challenge"),
+                    ("AuthType", "MD5"),
+                ])?;
+
+                let challenge_response = self.recv_response()?;
+                let challenge = match challenge_response.headers.get("Challenge") {
+                    Some(challenge) => challenge,
+                    None => {
+                        let response = challenge_response.headers.get("Message")
+                            .or_else(|| challenge_response.headers.get("Response"))
+                            .cloned()
+                            .unwrap_or_default();
+                        return Err(AmiError::ChallengeFailed(response));
+                    }
+                };
+
+                // The key is the md5 of the challenge followed by the secret, in lowercase hex.
+                key = format!("{:x}", md5::compute(format!("{}{}", challenge, secret)));
+
+                vec![
+                    ("Action", "Login"),
+                    ("AuthType", "MD5"),
+                    ("Username", username),
+                    ("Key", &key),
+                ]
+            },
+        };
+
+        if let Some(events) = events {
+            fields.push(("Events", events));
+        }
+        self.send_action(&fields)?;
+
+        // Lets get the login response.
+        let login_response = self.recv_response()?;
+
+        match login_response.headers.get("Response") {
+            Some(response) => {
+                if response != "Success" {
+                    return Err(AmiError::LoginFailed(response.clone()));
+                }
+            },
+            None => {
+                return Err(AmiError::NoLoginResponse);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Asks the server to only send events matching the regex, or to stop sending them if the regex starts with "!".
+    // Needs the "system" write permission and Asterisk 1.8 or newer.
+    pub fn add_filter(&mut self, filter: &str) -> Result<(), AmiError> {
+        let result = self.call(&[
+            ("Action", "Filter"),
+            ("Operation", "Add"),
+            ("Filter", filter),
+        ], self.timeout)?;
+
+        if !result.is_success() {
+            let message = result.response.headers.get("Message").cloned().unwrap_or_default();
+            return Err(AmiError::FilterRejected(filter.to_owned(), message));
+        }
+
+        Ok(())
+    }
+
+    // Writes an action to the server, each field on its own line followed by the empty line that ends the message.
+    // Nothing is tracked, use send or call to get the result of the action.
+    pub fn send_action(&mut self, fields: &[(&str, &str)]) -> Result<(), AmiError> {
+        let mut message = String::new();
+        for (name, value) in fields {
+            message.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        message.push_str("\r\n");
+
+        self.parser.get_mut().get_mut().write_all(message.as_bytes())?;
+        Ok(())
+    }
+
+    // Sends an action and returns the handle its result will arrive on.
+    // An ActionID is generated unless the fields already have one.
+    // The result is only filled in while this client keeps receiving, with recv or call.
+    pub fn send(&mut self, fields: &[(&str, &str)]) -> Result<ActionHandle, AmiError> {
+        let action_id = action::action_id_of(fields).unwrap_or_else(|| self.ids.next());
+        let (result, receiver) = mpsc::channel();
+
+        self.write_tracked(fields, &action_id, result)?;
+
+        Ok(ActionHandle::new(action_id, receiver))
+    }
+
+    // Sends an action and receives until its result is complete.
+    // Anything else that arrives in the meantime is kept and returned by the next calls to recv.
+    pub fn call(&mut self, fields: &[(&str, &str)], timeout: Duration) -> Result<ActionResult, AmiError> {
+        let handle = self.send(fields)?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(result) = handle.try_result() {
+                return Ok(result);
+            }
+            if Instant::now() >= deadline {
+                return Err(AmiError::Timeout);
+            }
+            if let Some(message) = self.read()? {
+                self.backlog.push_back(message);
+            }
+        }
+    }
+
+    // A sender other threads can use to send actions over this client.
+    pub fn action_sender(&self) -> ActionSender {
+        ActionSender::new(self.ids.clone(), self.queue_sender.clone())
+    }
+
+    // Receives the next message, or None if nothing complete arrived within the poll interval.
+    // Actions queued by ActionSenders are written first.
+    pub fn recv(&mut self) -> Result<Option<AmiMessage>, AmiError> {
+        while let Ok(action) = self.queue.try_recv() {
+            let fields: Vec<(&str, &str)> = action.fields.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+            self.write_tracked(&fields, &action.action_id, action.result)?;
+        }
+
+        if let Some(message) = self.backlog.pop_front() {
+            return Ok(Some(message));
+        }
+
+        self.read()
+    }
+
+    // Receives the next message, failing if it does not fully arrive within the timeout.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<AmiMessage, AmiError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(message) = self.recv()? {
+                return Ok(message);
+            }
+            if Instant::now() >= deadline {
+                return Err(AmiError::Timeout);
+            }
+        }
+    }
+
+    // Reads the next message from the server, routing it to its ActionHandle if it belongs to an action.
+    fn read(&mut self) -> Result<Option<AmiMessage>, AmiError> {
+        match self.parser.read_message()? {
+            Some(message) => Ok(self.tracker.route(message)),
+            None => Ok(None),
+        }
+    }
+
+    // Writes an action with the given ActionID and starts waiting for its result.
+    fn write_tracked(&mut self, fields: &[(&str, &str)], action_id: &str, result: Sender<ActionResult>) -> Result<(), AmiError> {
+        let mut fields: Vec<(&str, &str)> = fields.iter().filter(|(name, _)| !name.eq_ignore_ascii_case("ActionID")).cloned().collect();
+        fields.push(("ActionID", action_id));
+
+        self.tracker.register(action_id.to_owned(), result);
+        self.send_action(&fields)
+    }
+
+    // Receives the next response during login, where the server does not send events yet.
+    fn recv_response(&mut self) -> Result<Packet, AmiError> {
+        loop {
+            if let AmiMessage::Response(packet) = self.recv_timeout(self.timeout)? {
+                return Ok(packet);
+            }
+        }
+    }
+}
+
+// Opens the TCP connection, trying every address the host resolves to.
+fn open_tcp(host: &str, port: u16, timeout: Duration) -> Result<TcpStream, AmiError> {
+    let addrs = (host, port).to_socket_addrs().map_err(AmiError::ConnectError)?;
+
+    let mut last_error = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => {
+                return Ok(stream);
+            },
+            Err(e) => {
+                last_error = Some(e);
+            }
+        }
+    }
+
+    Err(AmiError::ConnectError(last_error.unwrap_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} did not resolve to any address", host))
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, io::{BufRead, BufReader, Write}, net::TcpListener, thread};
+
+    use super::*;
+
+    const SECRET: &str = "s3cret";
+    const CHALLENGE: &str = "840963270";
+
+    // A mock AMI server for a single connection: sends the banner and answers Challenge and Login like Asterisk does,
+    // accepting the user "admin" with SECRET. QueueStatus gets a list, mixed with events that are not part of it.
+    // Returns the port it listens on.
+    fn mock_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"Asterisk Call Manager/5.0.1\r\n").unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            loop {
+                let mut fields = HashMap::new();
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap() == 0 {
+                        return;
+                    }
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(": ") {
+                        fields.insert(name.to_owned(), value.to_owned());
+                    }
+                }
+
+                let reply = match fields.get("Action").map(String::as_str) {
+                    Some("Challenge") => format!("Response: Success\r\nChallenge: {}\r\n\r\n", CHALLENGE),
+                    Some("Login") => {
+                        let accepted = fields.get("Username").map(String::as_str) == Some("admin") && match fields.get("AuthType") {
+                            Some(_) => fields.get("Key") == Some(&format!("{:x}", md5::compute(format!("{}{}", CHALLENGE, SECRET)))),
+                            None => fields.get("Secret").map(String::as_str) == Some(SECRET),
+                        };
+                        if accepted {
+                            String::from("Response: Success\r\nMessage: Authentication accepted\r\n\r\n")
+                        } else {
+                            String::from("Response: Error\r\nMessage: Authentication failed\r\n\r\n")
+                        }
+                    },
+                    Some("QueueStatus") => {
+                        let action_id = fields.get("ActionID").cloned().unwrap_or_default();
+                        [
+                            String::from("Event: Newchannel\r\nChannel: SIP/100-00000001\r\n\r\n"),
+                            // A list event can beat the Response.
+                            format!("Event: QueueParams\r\nQueue: sales\r\nActionID: {}\r\n\r\n", action_id),
+                            format!("Response: Success\r\nActionID: {}\r\nEventList: start\r\nMessage: Queue status will follow\r\n\r\n", action_id),
+                            String::from("Event: Hangup\r\nChannel: SIP/100-00000001\r\n\r\n"),
+                            format!("Event: QueueMember\r\nQueue: sales\r\nActionID: {}\r\n\r\n", action_id),
+                            String::from("Event: QueueMember\r\nQueue: support\r\nActionID: someone-else\r\n\r\n"),
+                            format!("Event: QueueStatusComplete\r\nActionID: {}\r\nEventList: Complete\r\nListItems: 2\r\n\r\n", action_id),
+                        ].concat()
+                    },
+                    _ => String::from("Response: Error\r\nMessage: Invalid/unknown command\r\n\r\n"),
+                };
+                stream.write_all(reply.as_bytes()).unwrap();
+            }
+        });
+
+        port
+    }
+
+    fn connect() -> Result<AmiClient, AmiError> {
+        let options = ConnectOptions {
+            port: mock_server(),
+            timeout: Duration::from_secs(5),
+            poll_interval: Duration::from_millis(50),
+            ..ConnectOptions::default()
+        };
+        AmiClient::connect(&options)
+    }
+
+    fn login(auth: AuthType, secret: &str) -> Result<(), AmiError> {
+        let mut client = connect()?;
+        assert_eq!(client.version(), &"5.0.1".parse::<AmiVersion>().unwrap());
+        client.login("admin", secret, auth, None)
+    }
+
+    #[test]
+    fn plaintext_login() {
+        assert!(login(AuthType::Plaintext, SECRET).is_ok());
+    }
+
+    #[test]
+    fn plaintext_login_rejected() {
+        assert!(matches!(login(AuthType::Plaintext, "wrong"), Err(AmiError::LoginFailed(response)) if response == "Error"));
+    }
+
+    #[test]
+    fn md5_login() {
+        assert!(login(AuthType::Md5, SECRET).is_ok());
+    }
+
+    #[test]
+    fn md5_login_rejected() {
+        assert!(matches!(login(AuthType::Md5, "wrong"), Err(AmiError::LoginFailed(response)) if response == "Error"));
+    }
+
+    #[test]
+    fn call_collects_its_list() {
+        let mut client = connect().unwrap();
+        client.login("admin", SECRET, AuthType::Plaintext, None).unwrap();
+
+        let result = client.call(&[("Action", "QueueStatus")], Duration::from_secs(5)).unwrap();
+        assert!(result.is_success());
+        let events: Vec<(&str, &str)> = result.events
+            .iter()
+            .map(|event| (event.event_name().unwrap(), event.headers.get("Queue").map(String::as_str).unwrap_or_default()))
+            .collect();
+        assert_eq!(events, vec![("QueueParams", "sales"), ("QueueMember", "sales"), ("QueueStatusComplete", "")]);
+
+        // The events that are not part of the list come back from recv, in the order they arrived.
+        let mut others = vec![];
+        for _ in 0..3 {
+            match client.recv_timeout(Duration::from_secs(5)).unwrap() {
+                AmiMessage::Event(event) => others.push(format!("{} {}", event.event_name().unwrap(), event.action_id().unwrap_or_default())),
+                message => panic!("expected an event, got {:?}", message),
+            }
+        }
+        assert_eq!(others, vec!["Newchannel ", "Hangup ", "QueueMember someone-else"]);
+    }
+}
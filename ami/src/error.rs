@@ -0,0 +1,67 @@
+use std::{error::Error, fmt::{self, Display}, io};
+
+#[derive(Debug)]
+pub enum AmiError {
+    ConnectError(io::Error),
+    IoError(io::Error),
+    TlsError(String),
+    TlsConfigError(String),
+    LoginFailed(String),
+    NoLoginResponse,
+    ChallengeFailed(String),
+    FilterRejected(String, String),
+    Timeout,
+    UnknownBanner(String),
+}
+
+impl Error for AmiError {}
+
+impl Display for AmiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AmiError::ConnectError(e) => {
+                write!(f, "Unable to open TCP connection: {}", e)
+            },
+            AmiError::IoError(e) => {
+                write!(f, "Connection error: {}", e)
+            },
+            AmiError::TlsError(e) => {
+                write!(f, "TLS handshake failed: {}", e)
+            },
+            AmiError::TlsConfigError(e) => {
+                write!(f, "Invalid TLS settings: {}", e)
+            },
+            AmiError::LoginFailed(response) => {
+                write!(f, "Login failed with response: {}", response)
+            },
+            AmiError::NoLoginResponse => {
+                write!(f, "Unable to get login response.")
+            },
+            AmiError::ChallengeFailed(response) => {
+                write!(f, "Unable to get MD5 challenge, with response: {}", response)
+            },
+            AmiError::FilterRejected(filter, message) => {
+                write!(f, "Filter {:?} was rejected: {}", filter, message)
+            },
+            AmiError::Timeout => {
+                write!(f, "Timed out waiting for a response.")
+            },
+            AmiError::UnknownBanner(banner) => {
+                write!(f, "Server did not identify itself as an AMI server, banner: {:?}", banner)
+            },
+        }
+    }
+}
+
+impl AmiError {
+    // Errors that will not go away by connecting again, there is no point in retrying after them.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, AmiError::UnknownBanner(_) | AmiError::TlsConfigError(_))
+    }
+}
+
+impl From<io::Error> for AmiError {
+    fn from(e: io::Error) -> Self {
+        AmiError::IoError(e)
+    }
+}
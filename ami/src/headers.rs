@@ -0,0 +1,149 @@
+use serde::{Serialize, Serializer, ser::SerializeMap};
+
+// The headers of an AMI message, in the order the server sent them.
+// Unlike a HashMap this keeps repeated headers, like the ChanVariable and Variable lines of an event,
+// or the Output lines of a Command response.
+#[derive(Debug, Default, Clone)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn push(&mut self, name: String, value: String) {
+        self.entries.push((name, value));
+    }
+
+    // The first value of the header with the given name.
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.entries.iter().find(|(n, _)| n == name).map(|(_, value)| value)
+    }
+
+    // All the values of the header with the given name, in order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a String> + 'a {
+        self.entries.iter().filter(move |(n, _)| n == name).map(|(_, value)| value)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    // All the headers as (name, value), in order.
+    pub fn iter(&self) -> impl Iterator<Item = &(String, String)> {
+        self.entries.iter()
+    }
+
+    // Looks up a value using the addressing syntax of the event_data_link keys:
+    // - "Name" is the first value of the header.
+    // - "Name[n]" is the nth value of the header starting at 0, negative indexes count from the end so "Name[-1]" is the last one.
+    // - "Name(VAR)" looks for a "VAR=value" among the values of the header and returns the value,
+    //   e.g. "ChanVariable(FOO)" on "ChanVariable: FOO=bar" gives "bar". Older Asterisk versions
+    //   send "ChanVariable(SIP/100-00000001): FOO=bar", those headers are searched too.
+    // A header that is literally named like the key always wins, so unusual header names still work.
+    pub fn lookup(&self, key: &str) -> Option<&str> {
+        if let Some(value) = self.get(key) {
+            return Some(value);
+        }
+
+        if let Some((name, index)) = key.strip_suffix(']').and_then(|key| key.split_once('[')) {
+            let index: i64 = index.trim().parse().ok()?;
+            let values: Vec<&String> = self.entries.iter().filter(|(n, _)| n == name).map(|(_, value)| value).collect();
+            let index = if index < 0 { values.len() as i64 + index } else { index };
+            if index < 0 {
+                return None;
+            }
+            return values.get(index as usize).map(|value| value.as_str());
+        }
+
+        if let Some((name, variable)) = key.strip_suffix(')').and_then(|key| key.split_once('(')) {
+            let prefix = format!("{}(", name);
+            return self.entries
+                .iter()
+                .filter(|(n, _)| n == name || (n.starts_with(&prefix) && n.ends_with(')')))
+                .find_map(|(_, value)| {
+                    let (var, value) = value.split_once('=')?;
+                    if var == variable { Some(value) } else { None }
+                });
+        }
+
+        None
+    }
+}
+
+// In JSON the headers are an object in the order the server sent them.
+// A header that appears once is a string, a header that repeats is an array with all its values in order,
+// e.g. {"Event":"Newchannel","ChanVariable":["FOO=bar","BAZ=qux"]}.
+impl Serialize for Headers {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut names: Vec<&String> = vec![];
+        for (name, _) in &self.entries {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+
+        let mut map = serializer.serialize_map(Some(names.len()))?;
+        for name in names {
+            let values: Vec<&String> = self.get_all(name).collect();
+            if values.len() == 1 {
+                map.serialize_entry(name, values[0])?;
+            }
+            else {
+                map.serialize_entry(name, &values)?;
+            }
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(entries: &[(&str, &str)]) -> Headers {
+        let mut headers = Headers::default();
+        for (name, value) in entries {
+            headers.push(name.to_string(), value.to_string());
+        }
+        headers
+    }
+
+    #[test]
+    fn looks_up_by_name() {
+        let headers = headers(&[("Event", "Newchannel"), ("Output", "one"), ("Output", "two")]);
+        assert_eq!(headers.lookup("Event"), Some("Newchannel"));
+        assert_eq!(headers.lookup("Output"), Some("one"));
+        assert_eq!(headers.lookup("Missing"), None);
+    }
+
+    #[test]
+    fn looks_up_by_index() {
+        let headers = headers(&[("Output", "one"), ("Event", "Command"), ("Output", "two"), ("Output", "three")]);
+        assert_eq!(headers.lookup("Output[0]"), Some("one"));
+        assert_eq!(headers.lookup("Output[2]"), Some("three"));
+        assert_eq!(headers.lookup("Output[-1]"), Some("three"));
+        assert_eq!(headers.lookup("Output[-3]"), Some("one"));
+        assert_eq!(headers.lookup("Output[3]"), None);
+        assert_eq!(headers.lookup("Output[-4]"), None);
+        assert_eq!(headers.lookup("Output[x]"), None);
+    }
+
+    #[test]
+    fn looks_up_by_variable() {
+        let headers = headers(&[
+            ("ChanVariable", "FOO=bar"),
+            ("ChanVariable", "BAZ=a=b"),
+            ("ChanVariable(SIP/100-00000001)", "OLD=style"),
+        ]);
+        assert_eq!(headers.lookup("ChanVariable(FOO)"), Some("bar"));
+        assert_eq!(headers.lookup("ChanVariable(BAZ)"), Some("a=b"));
+        assert_eq!(headers.lookup("ChanVariable(OLD)"), Some("style"));
+        assert_eq!(headers.lookup("ChanVariable(NOPE)"), None);
+    }
+
+    #[test]
+    fn literal_header_names_win() {
+        let headers = headers(&[("Output", "one"), ("Output[1]", "literal"), ("Variable(X)", "literal too"), ("Variable", "X=parsed")]);
+        assert_eq!(headers.lookup("Output[1]"), Some("literal"));
+        assert_eq!(headers.lookup("Variable(X)"), Some("literal too"));
+    }
+}
@@ -0,0 +1,36 @@
+// A small library to talk to Asterisk servers over the Asterisk Manager Interface (AMI).
+//
+// The AMI protocol is quite simple, its based on the HTML header, each message is a list of "Name: Value" lines
+// and ends with a line containing only a carriage return. Right after connecting the server sends a single banner line.
+//
+// - Parser reads AmiMessages from anything that implements BufRead.
+// - AmiClient connects to a server (optionally over TLS), logs in, sends actions and receives messages.
+//   Actions get an ActionID, and the Response plus any list events that follow it are collected into an ActionResult.
+
+mod action;
+mod client;
+mod error;
+mod headers;
+mod message;
+mod stream;
+mod version;
+
+pub use action::{ActionHandle, ActionResult, ActionSender};
+pub use client::{AmiClient, ConnectOptions};
+pub use error::AmiError;
+pub use headers::Headers;
+pub use message::{AmiMessage, Packet, Parser};
+pub use stream::{AmiStream, TlsOptions};
+pub use version::AmiVersion;
+
+use serde::{Deserialize, Serialize};
+
+// How the client proves its identity when logging in.
+// Plaintext sends the secret as is, Md5 asks the server for a challenge and only sends md5(challenge + secret).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthType {
+    #[default]
+    Plaintext,
+    Md5,
+}
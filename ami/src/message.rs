@@ -0,0 +1,161 @@
+use serde::Serialize;
+use std::{io::{self, BufRead}, mem};
+
+use crate::headers::Headers;
+
+// The contents of a single AMI message: the "Name: Value" lines in order,
+// and any other line (like the output of a Command action on older servers) in rest.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Packet {
+    pub headers: Headers,
+    pub rest: String,
+}
+
+impl Packet {
+    // The value of the Event header, if this is an event.
+    pub fn event_name(&self) -> Option<&str> {
+        self.headers.get("Event").map(|event| event.as_str())
+    }
+
+    // The value of the ActionID header, set on everything the server sends in reply to an action that carried one.
+    pub fn action_id(&self) -> Option<&str> {
+        self.headers.get("ActionID").map(|action_id| action_id.as_str())
+    }
+
+    // The packet in the AMI wire format, the headers followed by the rest and the empty line that ends the message.
+    // Reading it back with a Parser gives the same packet.
+    pub fn to_wire(&self) -> String {
+        let mut text = String::new();
+        for (name, value) in self.headers.iter() {
+            text.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        text.push_str(&self.rest);
+        text.push_str("\r\n");
+        text
+    }
+}
+
+// A message received from an AMI server.
+#[derive(Debug, Clone)]
+pub enum AmiMessage {
+    // The single line the server sends when the connection opens, e.g. "Asterisk Call Manager/2.10.3".
+    Banner(String),
+    // The reply to an action.
+    Response(Packet),
+    // Something that happened on the server, unsolicited or as part of the reply to a list action.
+    Event(Packet),
+}
+
+impl AmiMessage {
+    // Classifies a packet, anything that is not an event is treated as a response.
+    pub fn from_packet(packet: Packet) -> AmiMessage {
+        if packet.headers.contains_key("Event") {
+            AmiMessage::Event(packet)
+        }
+        else {
+            AmiMessage::Response(packet)
+        }
+    }
+
+    pub fn packet(&self) -> Option<&Packet> {
+        match self {
+            AmiMessage::Banner(_) => None,
+            AmiMessage::Response(packet) | AmiMessage::Event(packet) => Some(packet),
+        }
+    }
+
+    pub fn into_packet(self) -> Option<Packet> {
+        match self {
+            AmiMessage::Banner(_) => None,
+            AmiMessage::Response(packet) | AmiMessage::Event(packet) => Some(packet),
+        }
+    }
+}
+
+// Reads AMI messages from any BufRead.
+// The parser has to be kept between calls, otherwise anything the reader buffered past the current message would be lost.
+// The underlying stream may have a read timeout, in that case the read functions return None and keep what they got so far,
+// so the caller can do something else (like sending a ping) and continue reading later.
+// If the stream ends an UnexpectedEof error is returned.
+pub struct Parser<R> {
+    reader: R,
+    line: Vec<u8>,
+    partial: Packet,
+}
+
+impl<R: BufRead> Parser<R> {
+    pub fn new(reader: R) -> Self {
+        Parser {
+            reader,
+            line: vec![],
+            partial: Packet::default(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    // Reads the banner line the server sends when the connection opens.
+    pub fn read_banner(&mut self) -> io::Result<Option<AmiMessage>> {
+        Ok(self.read_line()?.map(|line| AmiMessage::Banner(line.trim_end().to_owned())))
+    }
+
+    // Reads the next message, until the line with only a carriage return.
+    pub fn read_message(&mut self) -> io::Result<Option<AmiMessage>> {
+        loop {
+            let line = match self.read_line()? {
+                Some(line) => line,
+                None => {
+                    return Ok(None);
+                }
+            };
+
+            if line == "\r\n" || line == "\n" {
+                return Ok(Some(AmiMessage::from_packet(mem::take(&mut self.partial))));
+            }
+
+            // Lets check if the line contains a : and if it does, we will split it into the name and value for a header.
+            if let Some((name, value)) = line.split_once(':') {
+                self.partial.headers.push(
+                    name.trim().to_owned(),
+                    value.trim().to_owned()
+                );
+            }
+            else {
+                // Just add it to the rest of the message.
+                self.partial.rest.push_str(&line);
+            }
+        }
+    }
+
+    // Reads a full line, or returns None if the read timed out before the line was complete.
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        let s = match self.reader.read_until(b'\n', &mut self.line) {
+            Ok(s) => s,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+                return Ok(None);
+            },
+            Err(e) => {
+                return Err(e);
+            }
+        };
+
+        if s == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed by server"));
+        }
+
+        // The stream ended in the middle of the line, the next read will report the end.
+        if !self.line.ends_with(b"\n") {
+            return Ok(None);
+        }
+
+        let line = String::from_utf8_lossy(&self.line).into_owned();
+        self.line.clear();
+        Ok(Some(line))
+    }
+}
@@ -0,0 +1,102 @@
+use native_tls::{Certificate, Identity, TlsConnector, TlsStream};
+use std::{fs, io::{self, Read, Write}, net::TcpStream};
+
+use crate::error::AmiError;
+
+// How to verify the server, and how to identify ourselves, when connecting over TLS.
+#[derive(Debug, Clone)]
+pub struct TlsOptions {
+    // PEM bundle with the CA certificates used to verify the server, on top of the system ones.
+    pub ca_file: Option<String>,
+    // PEM certificate and PKCS#8 key presented to servers that require client authentication.
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    // Whether the server certificate has to match the host we connect to.
+    pub verify_hostname: bool,
+}
+
+impl Default for TlsOptions {
+    fn default() -> Self {
+        TlsOptions {
+            ca_file: None,
+            client_cert: None,
+            client_key: None,
+            verify_hostname: true,
+        }
+    }
+}
+
+// Reads a PEM file referenced from the options.
+fn read_pem(path: &str) -> Result<Vec<u8>, AmiError> {
+    fs::read(path).map_err(|e| AmiError::TlsConfigError(format!("Unable to read {}: {}", path, e)))
+}
+
+impl TlsOptions {
+    // Builds the TLS connector from the CA bundle, client certificate and verification options.
+    pub fn connector(&self) -> Result<TlsConnector, AmiError> {
+        let mut builder = TlsConnector::builder();
+
+        if let Some(ca_file) = &self.ca_file {
+            let ca = Certificate::from_pem(&read_pem(ca_file)?)
+                .map_err(|e| AmiError::TlsConfigError(format!("Invalid CA bundle {}: {}", ca_file, e)))?;
+            builder.add_root_certificate(ca);
+        }
+
+        match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => {
+                let identity = Identity::from_pkcs8(&read_pem(cert)?, &read_pem(key)?)
+                    .map_err(|e| AmiError::TlsConfigError(format!("Invalid client certificate {}: {}", cert, e)))?;
+                builder.identity(identity);
+            },
+            (None, None) => {},
+            _ => {
+                return Err(AmiError::TlsConfigError(String::from("client_cert and client_key have to be set together.")));
+            }
+        }
+
+        builder.danger_accept_invalid_hostnames(!self.verify_hostname);
+
+        builder.build().map_err(|e| AmiError::TlsConfigError(e.to_string()))
+    }
+}
+
+// The connection to an AMI server, either plain TCP or encrypted with TLS.
+pub enum AmiStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AmiStream {
+    // The underlying TCP connection, used to set socket options like timeouts.
+    pub fn tcp(&self) -> &TcpStream {
+        match self {
+            AmiStream::Plain(stream) => stream,
+            AmiStream::Tls(stream) => stream.get_ref(),
+        }
+    }
+}
+
+impl Read for AmiStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            AmiStream::Plain(stream) => stream.read(buf),
+            AmiStream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for AmiStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            AmiStream::Plain(stream) => stream.write(buf),
+            AmiStream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            AmiStream::Plain(stream) => stream.flush(),
+            AmiStream::Tls(stream) => stream.flush(),
+        }
+    }
+}
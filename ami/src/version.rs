@@ -0,0 +1,129 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{cmp::Ordering, fmt::{self, Display}, str::FromStr};
+
+// The banner every AMI server sends as soon as the connection is opened, e.g. "Asterisk Call Manager/2.10.3".
+const BANNER_PREFIX: &str = "Asterisk Call Manager/";
+
+// The AMI protocol version announced by a server in its banner.
+// Asterisk 1.4 - 1.8 send 1.1, newer releases send 2.x, 5.x, 7.x and so on, with a varying number of components.
+// Versions are compared component by component, missing components count as 0 so "2" equals "2.0.0".
+#[derive(Debug, Clone, Eq)]
+pub struct AmiVersion {
+    parts: Vec<u32>,
+}
+
+impl AmiVersion {
+    // Parses the banner line sent by the server, returns None if it is not an AMI banner.
+    pub fn from_banner(banner: &str) -> Option<AmiVersion> {
+        banner.trim().strip_prefix(BANNER_PREFIX)?.parse().ok()
+    }
+
+    // Whether this version falls under the given one, "2.10" matches 2.10, 2.10.0 and 2.10.3 but not 2.1.
+    pub fn matches(&self, pattern: &AmiVersion) -> bool {
+        pattern.parts.iter().enumerate().all(|(i, part)| self.parts.get(i).unwrap_or(&0) == part)
+    }
+}
+
+impl FromStr for AmiVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s
+            .trim()
+            .split('.')
+            .map(|part| part.parse::<u32>())
+            .collect::<Result<Vec<u32>, _>>()
+            .map_err(|_| format!("invalid AMI version \"{}\"", s))?;
+
+        Ok(AmiVersion { parts })
+    }
+}
+
+impl Display for AmiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parts: Vec<String> = self.parts.iter().map(|part| part.to_string()).collect();
+        write!(f, "{}", parts.join("."))
+    }
+}
+
+impl Ord for AmiVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.parts.len().max(other.parts.len());
+        for i in 0..len {
+            let ordering = self.parts.get(i).unwrap_or(&0).cmp(other.parts.get(i).unwrap_or(&0));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl PartialOrd for AmiVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for AmiVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+// In the settings file versions are written as plain strings, e.g. min_version = "2.0".
+impl Serialize for AmiVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for AmiVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(s: &str) -> AmiVersion {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_the_banner() {
+        assert_eq!(AmiVersion::from_banner("Asterisk Call Manager/2.10.3\r\n"), Some(version("2.10.3")));
+        assert_eq!(AmiVersion::from_banner("Asterisk Call Manager/1.1"), Some(version("1.1")));
+        assert_eq!(AmiVersion::from_banner("SSH-2.0-OpenSSH_8.2"), None);
+        assert_eq!(AmiVersion::from_banner("Asterisk Call Manager/2.x"), None);
+    }
+
+    #[test]
+    fn orders_component_by_component() {
+        assert!(version("1.1") < version("2.0"));
+        assert!(version("2.9") < version("2.10"));
+        assert!(version("2.10.3") > version("2.10"));
+        assert!(version("10.0.0") > version("7.0.3"));
+        assert_eq!(version("2"), version("2.0.0"));
+        assert_eq!(version("2.0.0").cmp(&version("2")), Ordering::Equal);
+    }
+
+    #[test]
+    fn matches_a_version_prefix() {
+        assert!(version("2.10.3").matches(&version("2.10")));
+        assert!(version("2.10").matches(&version("2.10")));
+        assert!(version("2.10").matches(&version("2.10.0")));
+        assert!(!version("2.1").matches(&version("2.10")));
+        assert!(!version("2.10.3").matches(&version("2.10.4")));
+        assert!(!version("5.0.1").matches(&version("2")));
+    }
+
+    #[test]
+    fn displays_as_written() {
+        assert_eq!(version(" 2.10.3 ").to_string(), "2.10.3");
+        assert!("2.a".parse::<AmiVersion>().is_err());
+    }
+}
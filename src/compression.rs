@@ -0,0 +1,94 @@
+use std::{fs::{self, File}, io::{self, BufRead, BufReader}, path::Path, sync::mpsc::{self, Sender}, thread};
+use flate2::{Compression as GzLevel, read::MultiGzDecoder, write::GzEncoder};
+
+use crate::settings::Compression;
+
+// The extensions a compressed log file gets on top of its own name.
+const EXTENSIONS: [&str; 2] = [".gz", ".zst"];
+
+impl Compression {
+    fn extension(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some(".gz"),
+            Compression::Zstd => Some(".zst"),
+        }
+    }
+}
+
+// Whether a compressed version of the log file exists.
+pub fn is_compressed(path: &str) -> bool {
+    EXTENSIONS.iter().any(|extension| Path::new(&format!("{}{}", path, extension)).exists())
+}
+
+// The name of the log file without the compression extension, if it has one.
+pub fn strip_extension(name: &str) -> &str {
+    EXTENSIONS.iter().find_map(|extension| name.strip_suffix(extension)).unwrap_or(name)
+}
+
+// Compresses the file next to itself and removes the original.
+// The compressed data is written to a temporary file first, so a crash never leaves a truncated archive behind.
+fn compress_file(path: &str, compression: Compression) -> io::Result<()> {
+    let extension = match compression.extension() {
+        Some(extension) => extension,
+        None => {
+            return Ok(());
+        }
+    };
+
+    let target = format!("{}{}", path, extension);
+    let temporary = format!("{}.tmp", target);
+
+    let mut input = File::open(path)?;
+    let output = File::create(&temporary)?;
+
+    match compression {
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(output, GzLevel::default());
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?.sync_all()?;
+        },
+        Compression::Zstd => {
+            let mut encoder = zstd::Encoder::new(output, 0)?;
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?.sync_all()?;
+        },
+        Compression::None => {},
+    }
+
+    fs::rename(&temporary, &target)?;
+    fs::remove_file(path)
+}
+
+// Starts the thread that compresses the log files sent to it once they are closed.
+// Returns None when compression is disabled.
+pub fn start_compressor(compression: Compression) -> Option<Sender<String>> {
+    compression.extension()?;
+
+    let (sender, receiver) = mpsc::channel::<String>();
+    thread::spawn(move || {
+        for path in receiver {
+            match compress_file(&path, compression) {
+                Ok(()) => println!("Compressed log file {}.", path),
+                Err(e) => println!("Unable to compress log file {} with error: {}", path, e),
+            }
+        }
+    });
+
+    Some(sender)
+}
+
+// Opens a log file for reading, decompressing it on the fly if its name ends with .gz or .zst.
+pub fn open_log(path: &str) -> io::Result<Box<dyn BufRead>> {
+    let file = File::open(path)?;
+
+    if path.ends_with(".gz") {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(file))))
+    }
+    else if path.ends_with(".zst") {
+        Ok(Box::new(BufReader::new(zstd::Decoder::new(file)?)))
+    }
+    else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
@@ -0,0 +1,400 @@
+use std::fmt::{self, Display};
+use regex::Regex;
+
+use crate::placeholder::ReceivedEvent;
+
+// The where of an event clause, an expression over the headers of the event:
+// - Queue == "sales", Cause != "16": the header equals the text, or does not.
+// - Duration > 30, HoldTime <= 5.5: compares the header as a number, with <, <=, >, >=, == or !=.
+// - Channel =~ "^SIP/", Channel !~ "^Local/": the header matches the regex, or does not.
+// - Queue: the header is there, !Queue: it is not.
+// - Joined with && and ||, negated with !, and grouped with ( ).
+// The headers use the same addressing as event_data_link, "ChanVariable(FOO)", "Output[1]", and placeholders like %SERVER_NAME% work too.
+// A header that is not there, or is not a number when compared to one, only matches != and !~.
+#[derive(Debug)]
+pub enum Condition {
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+    Not(Box<Condition>),
+    Present(String),
+    // The header, whether it should be equal, and the text.
+    Text(String, bool, String),
+    Number(String, Comparison, f64),
+    // The header, whether it should match, and the regex.
+    Regex(String, bool, Regex),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Key(String),
+    Text(String),
+    Number(f64),
+    Operator(&'static str),
+    Open,
+    Close,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Key(key) => write!(f, "{}", key),
+            Token::Text(text) => write!(f, "{:?}", text),
+            Token::Number(number) => write!(f, "{}", number),
+            Token::Operator(operator) => write!(f, "{}", operator),
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+        }
+    }
+}
+
+// What was found where something else was expected.
+fn found(token: Option<&Token>) -> String {
+    match token {
+        Some(token) => token.to_string(),
+        None => String::from("the end"),
+    }
+}
+
+// The longest first, so "<=" is not read as "<".
+const OPERATORS: [&str; 11] = ["==", "!=", "<=", ">=", "=~", "!~", "&&", "||", "<", ">", "!"];
+
+fn is_key_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '%' || c == '$'
+}
+
+fn is_key_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '%' | '$' | '-' | '.')
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '(' {
+            tokens.push(Token::Open);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::Close);
+            i += 1;
+        } else if c == '"' {
+            // Only \" and \\ are escapes, any other backslash is kept for the regexes.
+            let mut value = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(String::from("unterminated string")),
+                    Some('"') => break,
+                    Some('\\') if matches!(chars.get(i + 1), Some('"') | Some('\\')) => {
+                        value.push(chars[i + 1]);
+                        i += 2;
+                    },
+                    Some(c) => {
+                        value.push(*c);
+                        i += 1;
+                    },
+                }
+            }
+            tokens.push(Token::Text(value));
+            i += 1;
+        } else if c.is_ascii_digit() || (c == '-' && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit())) {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let number: String = chars[start..i].iter().collect();
+            tokens.push(Token::Number(number.parse().map_err(|_| format!("{:?} is not a number", number))?));
+        } else if is_key_start(c) {
+            let start = i;
+            while i < chars.len() && is_key_char(chars[i]) {
+                i += 1;
+            }
+            // "Output[1]" and "ChanVariable(FOO)" address a header, the bracket has to follow the name right away.
+            if let Some(close) = match chars.get(i) {
+                Some('[') => Some(']'),
+                Some('(') => Some(')'),
+                _ => None,
+            } {
+                match chars[i..].iter().position(|&c| c == close) {
+                    Some(end) => i += end + 1,
+                    None => return Err(format!("missing {:?} after {}", close, chars[start..i].iter().collect::<String>())),
+                }
+            }
+            tokens.push(Token::Key(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..].iter().collect();
+            match OPERATORS.iter().find(|operator| rest.starts_with(*operator)) {
+                Some(operator) => {
+                    tokens.push(Token::Operator(operator));
+                    i += operator.len();
+                },
+                None => return Err(format!("unexpected {:?}", c)),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, operator: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Operator(next)) if *next == operator) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    fn or(&mut self) -> Result<Condition, String> {
+        let mut condition = self.and()?;
+        while self.eat("||") {
+            condition = Condition::Or(Box::new(condition), Box::new(self.and()?));
+        }
+        Ok(condition)
+    }
+
+    fn and(&mut self) -> Result<Condition, String> {
+        let mut condition = self.not()?;
+        while self.eat("&&") {
+            condition = Condition::And(Box::new(condition), Box::new(self.not()?));
+        }
+        Ok(condition)
+    }
+
+    fn not(&mut self) -> Result<Condition, String> {
+        if self.eat("!") {
+            return Ok(Condition::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Condition, String> {
+        let key = match self.next() {
+            Some(Token::Open) => {
+                let condition = self.or()?;
+                if self.next() != Some(Token::Close) {
+                    return Err(String::from("missing \")\""));
+                }
+                return Ok(condition);
+            },
+            Some(Token::Key(key)) => key,
+            token => return Err(format!("expected a header, found {}", found(token.as_ref()))),
+        };
+
+        let operator = match self.peek() {
+            Some(Token::Operator(operator)) if !matches!(*operator, "&&" | "||" | "!") => *operator,
+            _ => return Ok(Condition::Present(key)),
+        };
+        self.position += 1;
+
+        let value = self.next();
+        match (operator, value) {
+            ("=~", Some(Token::Text(regex))) | ("!~", Some(Token::Text(regex))) => {
+                let regex = Regex::new(&regex).map_err(|e| format!("regex {:?}: {}", regex, e))?;
+                Ok(Condition::Regex(key, operator == "=~", regex))
+            },
+            ("==", Some(Token::Text(text))) | ("!=", Some(Token::Text(text))) => Ok(Condition::Text(key, operator == "==", text)),
+            (_, Some(Token::Number(number))) if operator != "=~" && operator != "!~" => {
+                let comparison = match operator {
+                    "==" => Comparison::Equal,
+                    "!=" => Comparison::NotEqual,
+                    "<" => Comparison::Less,
+                    "<=" => Comparison::LessOrEqual,
+                    ">" => Comparison::Greater,
+                    _ => Comparison::GreaterOrEqual,
+                };
+                Ok(Condition::Number(key, comparison, number))
+            },
+            (_, value) => Err(format!("{} {} needs {}, found {}", key, operator, match operator {
+                "=~" | "!~" => "a quoted regex",
+                "==" | "!=" => "a quoted text or a number",
+                _ => "a number",
+            }, found(value.as_ref()))),
+        }
+    }
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition, String> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            position: 0,
+        };
+        let condition = parser.or()?;
+        match parser.next() {
+            None => Ok(condition),
+            Some(token) => Err(format!("unexpected {}", token)),
+        }
+    }
+
+    pub fn matches(&self, received: &ReceivedEvent) -> bool {
+        match self {
+            Condition::And(left, right) => left.matches(received) && right.matches(received),
+            Condition::Or(left, right) => left.matches(received) || right.matches(received),
+            Condition::Not(condition) => !condition.matches(received),
+            Condition::Present(key) => received.text(key).is_some(),
+            Condition::Text(key, equal, text) => match received.text(key) {
+                Some(value) => (&value == text) == *equal,
+                None => !equal,
+            },
+            Condition::Regex(key, matching, regex) => match received.text(key) {
+                Some(value) => regex.is_match(&value) == *matching,
+                None => !matching,
+            },
+            Condition::Number(key, comparison, number) => {
+                let value = match received.text(key).and_then(|value| value.trim().parse::<f64>().ok()) {
+                    Some(value) => value,
+                    None => return *comparison == Comparison::NotEqual,
+                };
+                match comparison {
+                    Comparison::Equal => value == *number,
+                    Comparison::NotEqual => value != *number,
+                    Comparison::Less => value < *number,
+                    Comparison::LessOrEqual => value <= *number,
+                    Comparison::Greater => value > *number,
+                    Comparison::GreaterOrEqual => value >= *number,
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ami::{Headers, Packet};
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn packet(entries: &[(&str, &str)]) -> Packet {
+        let mut headers = Headers::default();
+        for (name, value) in entries {
+            headers.push(name.to_string(), value.to_string());
+        }
+        Packet {
+            headers,
+            rest: String::new(),
+        }
+    }
+
+    fn matches(condition: &str, event: &Packet) -> bool {
+        let received = ReceivedEvent {
+            server_name: "pbx1",
+            server_host: "10.0.0.1",
+            logger_host: "logger",
+            event,
+            received_at: Utc::now(),
+            uuid: Uuid::new_v4(),
+        };
+        Condition::parse(condition).unwrap().matches(&received)
+    }
+
+    #[test]
+    fn parses_with_precedence() {
+        // && binds tighter than ||, and ! tighter than both, so this is A || (B && (!C)).
+        let Ok(Condition::Or(left, right)) = Condition::parse("A || B && !C") else { panic!("expected an ||") };
+        assert!(matches!(*left, Condition::Present(key) if key == "A"));
+        let Condition::And(left, right) = *right else { panic!("expected an &&") };
+        assert!(matches!(*left, Condition::Present(key) if key == "B"));
+        assert!(matches!(*right, Condition::Not(not) if matches!(*not, Condition::Present(ref key) if key == "C")));
+
+        let Ok(Condition::And(left, _)) = Condition::parse("(A || B) && C") else { panic!("expected an &&") };
+        assert!(matches!(*left, Condition::Or(_, _)));
+    }
+
+    #[test]
+    fn parses_the_comparisons() {
+        assert!(matches!(Condition::parse(r#"Queue == "sales""#), Ok(Condition::Text(key, true, text)) if key == "Queue" && text == "sales"));
+        assert!(matches!(Condition::parse(r#"Cause != "16""#), Ok(Condition::Text(_, false, _))));
+        assert!(matches!(Condition::parse("Duration >= -1.5"), Ok(Condition::Number(_, Comparison::GreaterOrEqual, number)) if number == -1.5));
+        assert!(matches!(Condition::parse(r#"Channel !~ "^Local/""#), Ok(Condition::Regex(_, false, _))));
+        assert!(matches!(Condition::parse("ChanVariable(FOO) && Output[1]"), Ok(Condition::And(left, right))
+            if matches!(&*left, Condition::Present(key) if key == "ChanVariable(FOO)") && matches!(&*right, Condition::Present(key) if key == "Output[1]")));
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        for (text, error) in [
+            (r#"Queue == "sales"#, "unterminated string"),
+            ("(Queue", "missing \")\""),
+            ("Queue ==", "Queue == needs a quoted text or a number, found the end"),
+            ("Duration > \"30\"", "Duration > needs a number, found \"30\""),
+            (r#"Channel =~ "(""#, "regex \"(\""),
+            ("Queue Cause", "unexpected Cause"),
+            ("&& Queue", "expected a header, found &&"),
+            ("Queue # 1", "unexpected '#'"),
+        ] {
+            match Condition::parse(text) {
+                Ok(_) => panic!("{:?} should not parse", text),
+                Err(e) => assert!(e.starts_with(error), "{:?} gave {:?}", text, e),
+            }
+        }
+    }
+
+    #[test]
+    fn matches_the_headers() {
+        let event = packet(&[("Event", "QueueCallerLeave"), ("Queue", "sales"), ("Duration", " 42 "), ("Channel", "SIP/100-00000001")]);
+        assert!(matches(r#"Queue == "sales" && Duration > 30"#, &event));
+        assert!(matches(r#"Queue != "support""#, &event));
+        assert!(matches("Duration == 42 && Duration <= 42 && Duration < 42.5", &event));
+        assert!(matches(r#"Channel =~ "^SIP/" && Channel !~ "^Local/""#, &event));
+        assert!(matches(r#"Queue == "support" || !(Duration < 30)"#, &event));
+        assert!(!matches(r#"Queue == "Sales""#, &event));
+        assert!(!matches("Duration > 42", &event));
+        // A header that is not a number only matches !=.
+        assert!(!matches("Queue > 0", &event));
+        assert!(matches("Queue != 0", &event));
+    }
+
+    #[test]
+    fn missing_headers_only_match_the_negations() {
+        let event = packet(&[("Event", "Hangup")]);
+        assert!(!matches("Cause", &event));
+        assert!(matches("!Cause", &event));
+        assert!(!matches(r#"Cause == "16""#, &event));
+        assert!(matches(r#"Cause != "16""#, &event));
+        assert!(!matches(r#"Cause =~ ".""#, &event));
+        assert!(matches(r#"Cause !~ ".""#, &event));
+        assert!(!matches("Cause < 1", &event));
+        assert!(matches("Cause != 1", &event));
+    }
+
+    #[test]
+    fn matches_the_placeholders() {
+        let event = packet(&[("Event", "Hangup"), ("ChanVariable", "FOO=bar"), ("%SERVER_NAME%", "header wins")]);
+        assert!(matches(r#"%EVENT_NAME% == "Hangup" && %SERVER_HOST% == "10.0.0.1""#, &event));
+        assert!(matches(r#"%SERVER_NAME% == "header wins""#, &event));
+        assert!(matches(r#"ChanVariable(FOO) == "bar" && %RECEIVED_AT_MS% > 0"#, &event));
+        assert!(!matches("%NOT_A_PLACEHOLDER%", &event));
+    }
+}
@@ -0,0 +1,112 @@
+use chrono::{NaiveDateTime, TimeZone, Utc};
+
+use crate::{database::Value, settings::{Conversion, ValueType}};
+
+// How the datetimes are written to the databases, every driver takes this as a timestamp.
+pub const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+
+// Converts the value of an event to the type of its column, or says why it can not.
+pub fn convert(text: &str, conversion: &Conversion) -> Result<Value, String> {
+    let text = text.trim();
+    match conversion.value_type {
+        ValueType::Text => Ok(Value::Text(text.to_owned())),
+        ValueType::Int => text.parse().map(Value::Int).map_err(|_| format!("{:?} is not an int", text)),
+        ValueType::Float => text.parse().map(Value::Float).map_err(|_| format!("{:?} is not a float", text)),
+        ValueType::Bool => match text.to_ascii_lowercase().as_str() {
+            "yes" | "true" | "on" | "1" => Ok(Value::Bool(true)),
+            "no" | "false" | "off" | "0" => Ok(Value::Bool(false)),
+            _ => Err(format!("{:?} is not a bool", text)),
+        },
+        ValueType::Datetime => datetime(text, conversion.format.as_deref().unwrap_or("epoch")).map(Value::Text),
+        ValueType::Duration => duration(text).map(Value::Int).ok_or_else(|| format!("{:?} is not a duration", text)),
+    }
+}
+
+fn datetime(text: &str, format: &str) -> Result<String, String> {
+    let datetime = if format == "epoch" {
+        // A Uniqueid is "<seconds>.<counter>", only the seconds are a time.
+        let seconds = text.split('.').next().unwrap_or_default();
+        seconds
+            .parse()
+            .ok()
+            .and_then(|seconds| Utc.timestamp_opt(seconds, 0).single())
+            .map(|datetime| datetime.naive_utc())
+            .ok_or_else(|| format!("{:?} is not an epoch timestamp", text))?
+    } else {
+        NaiveDateTime::parse_from_str(text, format).map_err(|e| format!("{:?} does not match {:?}: {}", text, format, e))?
+    };
+    Ok(datetime.format(DATETIME_FORMAT).to_string())
+}
+
+// "90", "1:30" and "0:01:30" are all 90 seconds.
+fn duration(text: &str) -> Option<i64> {
+    let parts: Vec<&str> = text.split(':').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+
+    let mut seconds: i64 = 0;
+    for part in parts {
+        let part: i64 = part.parse().ok()?;
+        if part < 0 {
+            return None;
+        }
+        seconds = seconds.checked_mul(60)?.checked_add(part)?;
+    }
+    Some(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conversion(value_type: ValueType, format: Option<&str>) -> Conversion {
+        Conversion {
+            column: String::from("column"),
+            value_type,
+            format: format.map(String::from),
+        }
+    }
+
+    #[test]
+    fn converts_numbers() {
+        assert_eq!(convert(" 42 ", &conversion(ValueType::Int, None)), Ok(Value::Int(42)));
+        assert_eq!(convert("-7", &conversion(ValueType::Int, None)), Ok(Value::Int(-7)));
+        assert!(convert("4.2", &conversion(ValueType::Int, None)).is_err());
+        assert_eq!(convert("-3.5", &conversion(ValueType::Float, None)), Ok(Value::Float(-3.5)));
+        assert!(convert("abc", &conversion(ValueType::Float, None)).is_err());
+    }
+
+    #[test]
+    fn converts_text_and_bools() {
+        assert_eq!(convert(" SIP/100 ", &conversion(ValueType::Text, None)), Ok(Value::Text(String::from("SIP/100"))));
+        for text in ["yes", "True", "ON", "1"] {
+            assert_eq!(convert(text, &conversion(ValueType::Bool, None)), Ok(Value::Bool(true)));
+        }
+        for text in ["no", "False", "off", "0"] {
+            assert_eq!(convert(text, &conversion(ValueType::Bool, None)), Ok(Value::Bool(false)));
+        }
+        assert!(convert("maybe", &conversion(ValueType::Bool, None)).is_err());
+    }
+
+    #[test]
+    fn converts_datetimes() {
+        // The seconds of a Uniqueid, the counter after the "." is left out.
+        let expected = Ok(Value::Text(String::from("2021-10-16 12:00:00.000")));
+        assert_eq!(convert("1634385600.42", &conversion(ValueType::Datetime, None)), expected);
+        assert_eq!(convert("1634385600", &conversion(ValueType::Datetime, Some("epoch"))), expected);
+        assert_eq!(convert("16/10/2021 12:00:00", &conversion(ValueType::Datetime, Some("%d/%m/%Y %H:%M:%S"))), expected);
+        assert!(convert("yesterday", &conversion(ValueType::Datetime, None)).is_err());
+        assert!(convert("2021-10-16", &conversion(ValueType::Datetime, Some("%d/%m/%Y %H:%M:%S"))).is_err());
+    }
+
+    #[test]
+    fn converts_durations() {
+        for text in ["90", "1:30", "0:01:30"] {
+            assert_eq!(convert(text, &conversion(ValueType::Duration, None)), Ok(Value::Int(90)));
+        }
+        for text in ["", "1:-30", "1:2:3:4", "1m30s"] {
+            assert!(convert(text, &conversion(ValueType::Duration, None)).is_err(), "{:?}", text);
+        }
+    }
+}
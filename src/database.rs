@@ -0,0 +1,487 @@
+use std::{convert::TryFrom, error::Error, fmt::{self, Display}, sync::{Arc, Mutex}, time::Duration};
+use bytes::BytesMut;
+use mysql::{Opts, TxOpts, prelude::Queryable};
+use postgres::{NoTls, types::{Format, IsNull, ToSql, Type, to_sql_checked}};
+use rusqlite::types::{ToSqlOutput, ValueRef};
+use serde::{Deserialize, Serialize};
+
+use crate::settings::{DatabaseConnection, Driver, ValueType};
+
+// The most connections a MySQL pool opens, the same as mysql::Pool::new.
+const MYSQL_MAX_CONNECTIONS: usize = 100;
+
+// How long a write waits for a SQLite database file that is locked.
+const SQLITE_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The value of a column, the same for every driver.
+// Text is kept apart from other bytes so the spool stays readable.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Text(String),
+    Bytes(Vec<u8>),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+impl From<&Value> for mysql::Value {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Null => mysql::Value::NULL,
+            Value::Text(text) => mysql::Value::Bytes(text.as_bytes().to_vec()),
+            Value::Bytes(bytes) => mysql::Value::Bytes(bytes.clone()),
+            Value::Int(int) => mysql::Value::Int(*int),
+            Value::Float(float) => mysql::Value::Double(*float),
+            // MySQL booleans are TINYINT(1).
+            Value::Bool(bool) => mysql::Value::Int(*bool as i64),
+        }
+    }
+}
+
+// Values go to Postgres in the text format, so the server converts them to the type of the column
+// like it does for a literal in the statement, instead of the client insisting on a matching Rust type.
+impl ToSql for Value {
+    fn to_sql(&self, _ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        let text = match self {
+            Value::Null => {
+                return Ok(IsNull::Yes);
+            },
+            Value::Text(text) => text.clone(),
+            Value::Bytes(bytes) => format!("\\x{}", bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()),
+            Value::Int(int) => int.to_string(),
+            Value::Float(float) => float.to_string(),
+            Value::Bool(bool) => bool.to_string(),
+        };
+        out.extend_from_slice(text.as_bytes());
+        Ok(IsNull::No)
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+
+    fn encode_format(&self, _ty: &Type) -> Format {
+        Format::Text
+    }
+
+    to_sql_checked!();
+}
+
+impl rusqlite::ToSql for Value {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::Borrowed(match self {
+            Value::Null => ValueRef::Null,
+            Value::Text(text) => ValueRef::Text(text.as_bytes()),
+            Value::Bytes(bytes) => ValueRef::Blob(bytes),
+            Value::Int(int) => ValueRef::Integer(*int),
+            Value::Float(float) => ValueRef::Real(*float),
+            Value::Bool(bool) => ValueRef::Integer(*bool as i64),
+        }))
+    }
+}
+
+impl Driver {
+    fn name(&self) -> &'static str {
+        match self {
+            Driver::Mysql => "MySQL",
+            Driver::Postgres => "PostgreSQL",
+            Driver::Sqlite => "SQLite",
+        }
+    }
+
+    // The port the server listens on when the settings dont have one.
+    fn default_port(&self) -> u16 {
+        match self {
+            Driver::Mysql => 3306,
+            Driver::Postgres => 5432,
+            Driver::Sqlite => 0,
+        }
+    }
+
+    // Quotes a table or column name, every part of "schema.table" on its own.
+    pub fn quote(&self, identifier: &str) -> String {
+        identifier
+            .split('.')
+            .map(|part| match self {
+                Driver::Mysql => format!("`{}`", part.replace('`', "``")),
+                Driver::Postgres | Driver::Sqlite => format!("\"{}\"", part.replace('"', "\"\"")),
+            })
+            .collect::<Vec<String>>()
+            .join(".")
+    }
+
+    // The column types of the tables auto_schema creates, see settings::AutoSchema.
+    fn id_column(&self) -> &'static str {
+        match self {
+            Driver::Mysql => "BIGINT AUTO_INCREMENT PRIMARY KEY",
+            Driver::Postgres => "BIGSERIAL PRIMARY KEY",
+            Driver::Sqlite => "INTEGER PRIMARY KEY AUTOINCREMENT",
+        }
+    }
+
+    pub fn timestamp_column(&self) -> &'static str {
+        match self {
+            Driver::Mysql => "DATETIME(3)",
+            Driver::Postgres => "TIMESTAMP(3)",
+            // SQLite has no date type, the timestamps are kept as text.
+            Driver::Sqlite => "TEXT",
+        }
+    }
+
+    // The column type for the values of a conversion.
+    pub fn value_column(&self, value_type: ValueType) -> &'static str {
+        match (self, value_type) {
+            (_, ValueType::Text) => "TEXT",
+            (_, ValueType::Int) | (_, ValueType::Duration) => "BIGINT",
+            (Driver::Mysql, ValueType::Float) => "DOUBLE",
+            (Driver::Postgres, ValueType::Float) => "DOUBLE PRECISION",
+            (Driver::Sqlite, ValueType::Float) => "REAL",
+            (_, ValueType::Bool) => "BOOLEAN",
+            (_, ValueType::Datetime) => self.timestamp_column(),
+        }
+    }
+
+    // A CREATE TABLE for the (column, type) pairs, with an id column in front.
+    pub fn create_table(&self, table: &str, columns: &[(String, String)]) -> String {
+        let mut definitions = vec![format!("{} {}", self.quote("id"), self.id_column())];
+        for (column, sql_type) in columns {
+            definitions.push(format!("{} {}", self.quote(column), sql_type));
+        }
+        format!("CREATE TABLE IF NOT EXISTS {} ({})", self.quote(table), definitions.join(", "))
+    }
+
+    pub fn add_column(&self, table: &str, column: &str, sql_type: &str) -> String {
+        format!("ALTER TABLE {} ADD COLUMN {} {}", self.quote(table), self.quote(column), sql_type)
+    }
+
+    // Whether the column is one of the existing ones.
+    // Postgres identifiers are case sensitive once quoted, MySQL and SQLite column names never are.
+    pub fn has_column(&self, columns: &[String], column: &str) -> bool {
+        columns.iter().any(|existing| match self {
+            Driver::Postgres => existing == column,
+            _ => existing.eq_ignore_ascii_case(column),
+        })
+    }
+
+    // The placeholder of the nth value of a statement, starting at 1.
+    fn placeholder(&self, index: usize) -> String {
+        match self {
+            Driver::Mysql | Driver::Sqlite => String::from("?"),
+            Driver::Postgres => format!("${}", index),
+        }
+    }
+}
+
+// Rows for the same columns of a table, inserted with a single statement.
+pub struct Rows {
+    pub table: String,
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl Rows {
+    // A multi-row INSERT for the rows in the dialect of the driver, the values go in row by row.
+    fn statement(&self, driver: Driver) -> String {
+        let mut index = 0;
+        let rows: Vec<String> = self.rows
+            .iter()
+            .map(|_| {
+                let placeholders: Vec<String> = self.columns
+                    .iter()
+                    .map(|_| {
+                        index += 1;
+                        driver.placeholder(index)
+                    })
+                    .collect();
+                format!("({})", placeholders.join(","))
+            })
+            .collect();
+
+        format!(
+            "INSERT INTO {} ({}) VALUES {}",
+            driver.quote(&self.table),
+            self.columns.iter().map(|column| driver.quote(column)).collect::<Vec<String>>().join(","),
+            rows.join(",")
+        )
+    }
+
+    fn values(&self) -> impl Iterator<Item = &Value> {
+        self.rows.iter().flatten()
+    }
+}
+
+#[derive(Debug)]
+pub enum DatabaseError {
+    Mysql(mysql::Error),
+    Postgres(postgres::Error),
+    Sqlite(rusqlite::Error),
+    // The table of an event clause does not exist, (database, table).
+    MissingTable(String, String),
+    // A column of an event clause is not in its table, (database, table, column).
+    MissingColumn(String, String, String),
+}
+
+impl Error for DatabaseError {}
+
+impl DatabaseError {
+    // Whether the database rejected the rows for what is in them, like a NULL in a NOT NULL column, a duplicate key,
+    // or a value that does not fit the column. Trying again will never work, unlike when the database is down or busy.
+    // For MySQL and Postgres those are the SQLSTATE classes 22 (data exception) and 23 (integrity constraint violation).
+    pub fn is_rejected_row(&self) -> bool {
+        match self {
+            DatabaseError::Mysql(mysql::Error::MySqlError(e)) => e.state.starts_with("22") || e.state.starts_with("23"),
+            DatabaseError::Postgres(e) => e.code().is_some_and(|code| code.code().starts_with("22") || code.code().starts_with("23")),
+            DatabaseError::Sqlite(rusqlite::Error::SqliteFailure(e, _)) => matches!(
+                e.code,
+                rusqlite::ErrorCode::ConstraintViolation | rusqlite::ErrorCode::TypeMismatch | rusqlite::ErrorCode::TooBig
+            ),
+            DatabaseError::Sqlite(rusqlite::Error::ToSqlConversionFailure(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DatabaseError::Mysql(e) => write!(f, "{}", e),
+            // The Postgres errors keep the reason in their source, like "error connecting to server: Connection refused".
+            DatabaseError::Postgres(e) => match e.source() {
+                Some(source) => write!(f, "{}: {}", e, source),
+                None => write!(f, "{}", e),
+            },
+            DatabaseError::Sqlite(e) => write!(f, "{}", e),
+            DatabaseError::MissingTable(database, table) => {
+                write!(f, "Table {} does not exist in database {}.", table, database)
+            },
+            DatabaseError::MissingColumn(database, table, column) => {
+                write!(f, "Column {} does not exist in table {} of database {}.", column, table, database)
+            },
+        }
+    }
+}
+
+impl From<mysql::Error> for DatabaseError {
+    fn from(e: mysql::Error) -> Self {
+        DatabaseError::Mysql(e)
+    }
+}
+
+impl From<postgres::Error> for DatabaseError {
+    fn from(e: postgres::Error) -> Self {
+        DatabaseError::Postgres(e)
+    }
+}
+
+impl From<rusqlite::Error> for DatabaseError {
+    fn from(e: rusqlite::Error) -> Self {
+        DatabaseError::Sqlite(e)
+    }
+}
+
+// A single Postgres connection shared by the sinks of a database, opened again when it is lost.
+pub struct PostgresConnection {
+    config: postgres::Config,
+    client: Option<postgres::Client>,
+}
+
+impl PostgresConnection {
+    fn client(&mut self) -> Result<&mut postgres::Client, postgres::Error> {
+        if self.client.as_ref().is_none_or(|client| client.is_closed()) {
+            self.client = Some(self.config.connect(NoTls)?);
+        }
+        Ok(self.client.as_mut().unwrap())
+    }
+}
+
+// The SQLite database file, opened again if opening it failed.
+pub struct SqliteConnection {
+    path: String,
+    connection: Option<rusqlite::Connection>,
+}
+
+impl SqliteConnection {
+    fn connection(&mut self) -> Result<&mut rusqlite::Connection, rusqlite::Error> {
+        if self.connection.is_none() {
+            let connection = rusqlite::Connection::open(&self.path)?;
+            // Other processes (like a test reading the rows) may hold the file for a moment.
+            connection.busy_timeout(SQLITE_BUSY_TIMEOUT)?;
+            self.connection = Some(connection);
+        }
+        Ok(self.connection.as_mut().unwrap())
+    }
+}
+
+// The connections to a database, cloning it shares them.
+#[derive(Clone)]
+pub enum Pool {
+    Mysql(mysql::Pool),
+    Postgres(Arc<Mutex<PostgresConnection>>),
+    Sqlite(Arc<Mutex<SqliteConnection>>),
+}
+
+impl Pool {
+    // Connects to the database, reporting why if it fails.
+    // With the spool enabled a database that is down still gets a pool, that connects once the database is reachable.
+    pub fn connect(database: &DatabaseConnection) -> Option<Pool> {
+        let name = database.driver.name();
+        // A SQLite database is a file, there is no host.
+        let location = match database.driver {
+            Driver::Sqlite => &database.database,
+            _ => &database.host,
+        };
+        println!("Connecting to {} database {}.", name, location);
+
+        let result = match database.driver {
+            Driver::Mysql => Pool::connect_mysql(database),
+            Driver::Postgres => Pool::connect_postgres(database),
+            Driver::Sqlite => Pool::connect_sqlite(database),
+        };
+
+        match result {
+            Ok(pool) => {
+                println!("Connected successfully to database {}.", location);
+                Some(pool)
+            },
+            Err((e, pool)) => {
+                println!("Unable to connect to {} database {} with error: {}", name, location, e);
+                if database.spool.max_bytes == 0 {
+                    return None;
+                }
+
+                println!("Spooling the rows of database {} until it is reachable.", database.id);
+                pool
+            },
+        }
+    }
+
+    fn port(database: &DatabaseConnection) -> u16 {
+        match database.port {
+            0 => database.driver.default_port(),
+            port => u16::try_from(port).expect("the port is validated with the settings"),
+        }
+    }
+
+    // On failure, also returns the pool to use until the database is reachable, if there can be one.
+    fn connect_mysql(database: &DatabaseConnection) -> Result<Pool, (String, Option<Pool>)> {
+        let url = format!("mysql://{}:{}@{}:{}/{}", database.user, database.password, database.host, Pool::port(database), database.database);
+        let opts = Opts::from_url(&url).map_err(|e| (e.to_string(), None))?;
+
+        match mysql::Pool::new(opts.clone()) {
+            Ok(pool) => Ok(Pool::Mysql(pool)),
+            // A pool that starts without connections.
+            Err(e) => Err((e.to_string(), mysql::Pool::new_manual(0, MYSQL_MAX_CONNECTIONS, opts).ok().map(Pool::Mysql))),
+        }
+    }
+
+    fn connect_postgres(database: &DatabaseConnection) -> Result<Pool, (String, Option<Pool>)> {
+        let mut config = postgres::Config::new();
+        config
+            .host(&database.host)
+            .port(Pool::port(database))
+            .user(&database.user)
+            .password(&database.password)
+            .dbname(&database.database);
+
+        let mut connection = PostgresConnection {
+            config,
+            client: None,
+        };
+        let result = connection.client().map(|_| ()).map_err(|e| e.to_string());
+        let pool = Pool::Postgres(Arc::new(Mutex::new(connection)));
+
+        match result {
+            Ok(()) => Ok(pool),
+            Err(e) => Err((e, Some(pool))),
+        }
+    }
+
+    fn connect_sqlite(database: &DatabaseConnection) -> Result<Pool, (String, Option<Pool>)> {
+        let mut connection = SqliteConnection {
+            path: database.database.clone(),
+            connection: None,
+        };
+        let result = connection.connection().map(|_| ()).map_err(|e| e.to_string());
+        let pool = Pool::Sqlite(Arc::new(Mutex::new(connection)));
+
+        match result {
+            Ok(()) => Ok(pool),
+            Err(e) => Err((e, Some(pool))),
+        }
+    }
+
+    // The column names of the table, empty if the table does not exist.
+    pub fn columns(&self, table: &str) -> Result<Vec<String>, DatabaseError> {
+        let (schema, table) = match table.split_once('.') {
+            Some((schema, table)) => (Some(schema), table),
+            None => (None, table),
+        };
+
+        let columns = match self {
+            Pool::Mysql(pool) => {
+                let mut conn = pool.get_conn()?;
+                conn.exec(
+                    "SELECT COLUMN_NAME FROM information_schema.COLUMNS WHERE TABLE_SCHEMA = COALESCE(?, DATABASE()) AND TABLE_NAME = ?",
+                    (schema, table),
+                )?
+            },
+            Pool::Postgres(connection) => {
+                let mut connection = connection.lock().unwrap();
+                connection.client()?
+                    .query(
+                        "SELECT column_name::text FROM information_schema.columns WHERE table_schema = COALESCE($1, current_schema()) AND table_name = $2",
+                        &[&schema, &table],
+                    )?
+                    .iter()
+                    .map(|row| row.get(0))
+                    .collect()
+            },
+            Pool::Sqlite(connection) => {
+                let mut connection = connection.lock().unwrap();
+                let connection = connection.connection()?;
+                let mut statement = connection.prepare("SELECT name FROM pragma_table_info(?1, ?2)")?;
+                let rows = statement.query_map(rusqlite::params![table, schema.unwrap_or("main")], |row| row.get(0))?;
+                rows.collect::<Result<Vec<String>, rusqlite::Error>>()?
+            },
+        };
+        Ok(columns)
+    }
+
+    // Runs a statement that returns nothing, like the DDL of auto_schema.
+    pub fn execute(&self, statement: &str) -> Result<(), DatabaseError> {
+        match self {
+            Pool::Mysql(pool) => pool.get_conn()?.query_drop(statement)?,
+            Pool::Postgres(connection) => connection.lock().unwrap().client()?.batch_execute(statement)?,
+            Pool::Sqlite(connection) => connection.lock().unwrap().connection()?.execute_batch(statement)?,
+        }
+        Ok(())
+    }
+
+    // Inserts the rows of a batch in their own transaction, if anything fails the transaction is rolled back.
+    pub fn insert(&self, batch: &Rows) -> Result<(), DatabaseError> {
+        match self {
+            Pool::Mysql(pool) => {
+                let mut conn = pool.get_conn()?;
+                let mut transaction = conn.start_transaction(TxOpts::default())?;
+                let values: Vec<mysql::Value> = batch.values().map(mysql::Value::from).collect();
+                transaction.exec_drop(batch.statement(Driver::Mysql), values)?;
+                transaction.commit()?;
+            },
+            Pool::Postgres(connection) => {
+                let mut connection = connection.lock().unwrap();
+                let mut transaction = connection.client()?.transaction()?;
+                let values: Vec<&(dyn ToSql + Sync)> = batch.values().map(|value| value as &(dyn ToSql + Sync)).collect();
+                transaction.execute(batch.statement(Driver::Postgres).as_str(), &values)?;
+                transaction.commit()?;
+            },
+            Pool::Sqlite(connection) => {
+                let mut connection = connection.lock().unwrap();
+                let transaction = connection.connection()?.transaction()?;
+                transaction.execute(&batch.statement(Driver::Sqlite), rusqlite::params_from_iter(batch.values()))?;
+                transaction.commit()?;
+            },
+        }
+        Ok(())
+    }
+}
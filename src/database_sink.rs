@@ -0,0 +1,570 @@
+use std::{iter, mem, time::{Duration, Instant}};
+
+use crate::{condition::Condition, conversion::convert, database::{DatabaseError, Pool, Rows, Value}, placeholder::{ReceivedEvent, placeholder_type}, settings::{AutoSchema, Batch, ConversionPolicy, DataLink, DatabaseConnection, Driver, EventClause}, sink::Sink, spool::{SpoolFile, SpooledRow, quarantine}};
+
+// The columns auto_schema adds to every table, with the event key filling them in.
+const SERVER_COLUMN: (&str, &str) = ("%SERVER_NAME%", "server");
+const RECEIVED_AT_COLUMN: (&str, &str) = ("%RECEIVED_AT%", "received_at");
+
+fn spooled(batch: &Rows) -> Vec<SpooledRow> {
+    batch.rows
+        .iter()
+        .map(|row| SpooledRow {
+            table: batch.table.clone(),
+            columns: batch.columns.clone(),
+            values: row.clone(),
+        })
+        .collect()
+}
+
+// Groups the spooled rows back into Rows, keeping their order, each with the spool offset right after every row.
+fn unspool(spooled: Vec<(SpooledRow, u64)>) -> Vec<(Rows, Vec<u64>)> {
+    let mut batches: Vec<(Rows, Vec<u64>)> = vec![];
+    for (row, offset) in spooled {
+        match batches.last_mut() {
+            Some((last, offsets)) if last.table == row.table && last.columns == row.columns => {
+                last.rows.push(row.values);
+                offsets.push(offset);
+            },
+            _ => batches.push((
+                Rows {
+                    table: row.table,
+                    columns: row.columns,
+                    rows: vec![row.values],
+                },
+                vec![offset],
+            )),
+        }
+    }
+    batches
+}
+
+// The rows of an event clause waiting to be inserted.
+struct PendingClause {
+    event_clause: EventClause,
+    condition: Option<Condition>,
+    // The event_data_link as (event key, link), in the order of the values of each row.
+    links: Vec<(String, DataLink)>,
+    rows: Vec<Vec<Value>>,
+    // When the oldest row in rows came in.
+    since: Option<Instant>,
+}
+
+impl PendingClause {
+    fn new(event_clause: EventClause, auto_schema: AutoSchema) -> Self {
+        let mut links: Vec<(String, DataLink)> = event_clause.event_data_link.iter().map(|(key, link)| (key.clone(), link.clone())).collect();
+        // The event_data_link is a HashMap, lets sort it by column so the CREATE TABLE comes out the same on every run.
+        links.sort_by(|(_, a), (_, b)| a.column().cmp(b.column()));
+
+        // The tables of auto_schema have a server and a received_at column, unless the event_data_link already fills them.
+        if auto_schema != AutoSchema::Off {
+            for (key, column) in [SERVER_COLUMN, RECEIVED_AT_COLUMN] {
+                if !links.iter().any(|(_, existing)| existing.column().eq_ignore_ascii_case(column)) {
+                    links.push((key.to_owned(), DataLink::Column(column.to_owned())));
+                }
+            }
+        }
+
+        PendingClause {
+            links,
+            condition: event_clause.condition.as_deref().map(|condition| Condition::parse(condition).expect("the where is validated with the settings")),
+            event_clause,
+            rows: vec![],
+            since: None,
+        }
+    }
+
+    fn matches(&self, received: &ReceivedEvent) -> bool {
+        received.event.event_name() == Some(self.event_clause.event_name.as_str())
+            && self.condition.as_ref().is_none_or(|condition| condition.matches(received))
+    }
+
+    // The values of the columns for the event, none if a value can not be converted and the clause leaves such rows out.
+    fn row(&self, received: &ReceivedEvent) -> Option<Vec<Value>> {
+        let mut row = Vec::with_capacity(self.links.len());
+        for (event_key, link) in &self.links {
+            // Lets check if the event_key addresses a value in the event headers.
+            let value = match received.event.headers.lookup(event_key) {
+                Some(value) => match link.conversion() {
+                    Some(conversion) => match convert(value, conversion) {
+                        Ok(value) => value,
+                        Err(e) => match self.event_clause.on_conversion_error {
+                            ConversionPolicy::Null => Value::Null,
+                            ConversionPolicy::Skip => return None,
+                            ConversionPolicy::Error => {
+                                println!("Unable to convert {} of event {} for table {}: {}", event_key, self.event_clause.event_name, self.event_clause.db_table, e);
+                                return None;
+                            },
+                        },
+                    },
+                    None => Value::Text(value.to_owned()),
+                },
+                // If the event_key is a placeholder like %SERVER_NAME% we will add its value.
+                None => received.placeholder(event_key).unwrap_or(Value::Null),
+            };
+            row.push(value);
+        }
+        Some(row)
+    }
+
+    // The columns of the table with their SQL types, the ones not in column_types get the type of their conversion.
+    fn column_types(&self, driver: Driver) -> Vec<(String, String)> {
+        self.links
+            .iter()
+            .map(|(key, link)| {
+                let sql_type = match (self.event_clause.column_types.get(link.column()), link.conversion()) {
+                    (Some(sql_type), _) => sql_type.as_str(),
+                    (None, Some(conversion)) => driver.value_column(conversion.value_type),
+                    (None, None) => driver.value_column(placeholder_type(key)),
+                };
+                (link.column().to_owned(), sql_type.to_owned())
+            })
+            .collect()
+    }
+
+    // Takes the buffered rows out.
+    fn take(&mut self) -> Rows {
+        self.since = None;
+        Rows {
+            table: self.event_clause.db_table.clone(),
+            columns: self.links.iter().map(|(_, link)| link.column().to_owned()).collect(),
+            rows: mem::take(&mut self.rows),
+        }
+    }
+}
+
+// How long the batches took to insert, since the start.
+#[derive(Default)]
+struct BatchMetrics {
+    batches: u64,
+    rows: u64,
+    total: Duration,
+    max: Duration,
+}
+
+impl BatchMetrics {
+    fn record(&mut self, rows: usize, latency: Duration) {
+        self.batches += 1;
+        self.rows += rows as u64;
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+
+    fn average(&self) -> Duration {
+        if self.batches == 0 {
+            return Duration::default();
+        }
+        self.total / self.batches as u32
+    }
+}
+
+// Writes the events matching the event_clauses of a database connection as rows of their tables.
+// The rows are buffered per event clause and inserted in batches, see settings::Batch.
+// Rows that can not be inserted go to the spool, see settings::Spool, and rows the database rejects to the quarantine.
+pub struct DatabaseSink {
+    id: String,
+    pool: Pool,
+    batch: Batch,
+    clauses: Vec<PendingClause>,
+    metrics: BatchMetrics,
+    spool: Option<SpoolFile>,
+    // The directory of the spool settings, where the quarantine goes.
+    quarantine: String,
+    retry_interval: Duration,
+    // After a failure nothing is sent to the database until then, the rows go straight to the spool.
+    retry_at: Option<Instant>,
+}
+
+impl DatabaseSink {
+    pub fn new(database: &DatabaseConnection, pool: Pool, event_clauses: &[EventClause]) -> Result<Self, DatabaseError> {
+        let spool = if database.spool.max_bytes > 0 {
+            match SpoolFile::open(&database.id, &database.spool) {
+                Ok(spool) => Some(spool),
+                Err(e) => {
+                    println!("Unable to open the spool of database {} with error: {}", database.id, e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let sink = DatabaseSink {
+            id: database.id.clone(),
+            pool,
+            batch: database.batch.clone(),
+            clauses: event_clauses
+                .iter()
+                .filter(|event_clause| event_clause.db_connection_id == database.id)
+                .cloned()
+                .map(|event_clause| PendingClause::new(event_clause, database.auto_schema))
+                .collect(),
+            metrics: BatchMetrics::default(),
+            spool,
+            quarantine: database.spool.directory.clone(),
+            retry_interval: Duration::from_millis(database.spool.retry_interval_ms),
+            retry_at: None,
+        };
+        if database.auto_schema != AutoSchema::Off {
+            sink.migrate(database.driver, database.auto_schema == AutoSchema::DryRun)?;
+        }
+        sink.check_columns(database.driver)?;
+        Ok(sink)
+    }
+
+    // Creates the tables of the event clauses that do not exist, and adds the columns they are missing.
+    // With dry_run the statements are only printed, the column check that follows then reports what is missing.
+    fn migrate(&self, driver: Driver, dry_run: bool) -> Result<(), DatabaseError> {
+        for clause in &self.clauses {
+            let table = &clause.event_clause.db_table;
+            let columns = match self.pool.columns(table) {
+                Ok(columns) => columns,
+                Err(e) => {
+                    println!("Unable to check the columns of table {} in database {} with error: {}", table, &self.id, e);
+                    continue;
+                }
+            };
+
+            let statements = if columns.is_empty() {
+                vec![driver.create_table(table, &clause.column_types(driver))]
+            } else {
+                clause.column_types(driver)
+                    .iter()
+                    .filter(|(column, _)| !driver.has_column(&columns, column))
+                    .map(|(column, sql_type)| driver.add_column(table, column, sql_type))
+                    .collect()
+            };
+
+            for statement in statements {
+                if dry_run {
+                    println!("Dry run for database {}: {};", &self.id, statement);
+                    continue;
+                }
+
+                println!("Running on database {}: {};", &self.id, statement);
+                self.pool.execute(&statement)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Makes sure the tables and columns of the event clauses exist, so a typo is reported at startup instead of on every event.
+    // A database that is not reachable can not be checked, its rows are spooled anyway.
+    fn check_columns(&self, driver: Driver) -> Result<(), DatabaseError> {
+        for clause in &self.clauses {
+            let table = &clause.event_clause.db_table;
+            let columns = match self.pool.columns(table) {
+                Ok(columns) => columns,
+                Err(e) => {
+                    println!("Unable to check the columns of table {} in database {} with error: {}", table, &self.id, e);
+                    continue;
+                }
+            };
+
+            if columns.is_empty() {
+                return Err(DatabaseError::MissingTable(self.id.clone(), table.clone()));
+            }
+
+            for (_, link) in &clause.links {
+                if !driver.has_column(&columns, link.column()) {
+                    return Err(DatabaseError::MissingColumn(self.id.clone(), table.clone(), link.column().to_owned()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn spool(&mut self, batches: &[Rows]) {
+        let rows: Vec<SpooledRow> = batches.iter().flat_map(spooled).collect();
+        let result = match &mut self.spool {
+            Some(spool) => spool.append(&rows),
+            None => {
+                println!("Dropping {} rows for database {}.", rows.len(), &self.id);
+                return;
+            }
+        };
+
+        if let Err(e) = result {
+            println!("Unable to spool {} rows for database {} with error: {}", rows.len(), &self.id, e);
+        }
+    }
+
+    fn advance(&mut self, offset: u64) {
+        if let Some(spool) = &mut self.spool {
+            if let Err(e) = spool.advance(offset) {
+                println!("Unable to update the spool of database {} with error: {}", &self.id, e);
+            }
+        }
+    }
+
+    // Inserts the batch in its own transaction, so a clause with a bad row does not hold up the others, and returns how
+    // many rows made it in. When the database rejects the batch for what is in it the rows are tried one by one, and the
+    // ones it still rejects are quarantined. On any other error it returns how many rows made it in before it, the rest
+    // have to be tried again later.
+    fn insert_batch(&self, batch: &Rows) -> Result<usize, (usize, DatabaseError)> {
+        match self.pool.insert(batch) {
+            Ok(()) => return Ok(batch.rows.len()),
+            Err(e) if !e.is_rejected_row() => return Err((0, e)),
+            Err(_) => {},
+        }
+
+        let mut inserted = 0;
+        for (index, values) in batch.rows.iter().enumerate() {
+            let row = Rows {
+                table: batch.table.clone(),
+                columns: batch.columns.clone(),
+                rows: vec![values.clone()],
+            };
+            match self.pool.insert(&row) {
+                Ok(()) => inserted += 1,
+                Err(e) if e.is_rejected_row() => {
+                    println!("Quarantining a row rejected by database {} table {} with error: {}", &self.id, &batch.table, e);
+                    if let Err(e) = quarantine(&self.quarantine, &self.id, &spooled(&row), &e.to_string()) {
+                        println!("Unable to quarantine a row for database {} with error: {}", &self.id, e);
+                    }
+                },
+                Err(e) => return Err((index, e)),
+            }
+        }
+        Ok(inserted)
+    }
+
+    // Replays the spool in order, returns whether the database is reachable and the spool empty,
+    // so the new rows can be inserted without overtaking the spooled ones.
+    fn replay(&mut self) -> bool {
+        if let Some(retry_at) = self.retry_at {
+            if Instant::now() < retry_at {
+                return false;
+            }
+            self.retry_at = None;
+        }
+
+        loop {
+            let (rows, offset) = match &self.spool {
+                Some(spool) if !spool.is_empty() => match spool.read(self.batch.size.max(1)) {
+                    Ok(read) => read,
+                    Err(e) => {
+                        println!("Unable to read the spool of database {} with error: {}", &self.id, e);
+                        self.retry_at = Some(Instant::now() + self.retry_interval);
+                        return false;
+                    }
+                },
+                _ => return true,
+            };
+
+            // Every batch is marked as replayed once it is done with, so a failure only leaves the rows after it.
+            for (batch, offsets) in unspool(rows) {
+                match self.insert_batch(&batch) {
+                    Ok(inserted) => {
+                        self.advance(offsets[offsets.len() - 1]);
+                        println!("Replayed {} spooled rows into database {} table {}.", inserted, &self.id, &batch.table);
+                    },
+                    Err((handled, e)) => {
+                        if handled > 0 {
+                            self.advance(offsets[handled - 1]);
+                        }
+                        println!("Unable to replay {} spooled rows into database {} table {} with error: {}", batch.rows.len() - handled, &self.id, &batch.table, e);
+                        self.retry_at = Some(Instant::now() + self.retry_interval);
+                        return false;
+                    },
+                }
+            }
+            // Past the unreadable lines after the last row, if any.
+            self.advance(offset);
+        }
+    }
+
+    // Inserts the buffered rows of the clauses, each in its own transaction, or spools them if that is not possible.
+    fn insert(&mut self, indexes: &[usize]) {
+        let indexes: Vec<usize> = indexes.iter().copied().filter(|&index| !self.clauses[index].rows.is_empty()).collect();
+        if indexes.is_empty() {
+            return;
+        }
+
+        let batches: Vec<Rows> = indexes.iter().map(|&index| self.clauses[index].take()).collect();
+        if !self.replay() {
+            self.spool(&batches);
+            return;
+        }
+
+        let mut batches = batches.into_iter();
+        while let Some(mut batch) = batches.next() {
+            let start = Instant::now();
+            match self.insert_batch(&batch) {
+                Ok(inserted) => {
+                    let latency = start.elapsed();
+                    self.metrics.record(inserted, latency);
+                    println!(
+                        "Inserted {} rows into database {} table {} in {}ms ({} batches, {} rows, avg {}ms, max {}ms).",
+                        inserted, &self.id, &batch.table, latency.as_millis(),
+                        self.metrics.batches, self.metrics.rows, self.metrics.average().as_millis(), self.metrics.max.as_millis()
+                    );
+                },
+                Err((handled, e)) => {
+                    // The database is not reachable, the rest of this batch and the batches after it are spooled.
+                    batch.rows.drain(..handled);
+                    println!("Unable to insert {} rows into database {} table {} with error: {}", batch.rows.len(), &self.id, &batch.table, e);
+                    self.retry_at = Some(Instant::now() + self.retry_interval);
+                    let rest: Vec<Rows> = iter::once(batch).chain(batches).collect();
+                    self.spool(&rest);
+                    return;
+                },
+            }
+        }
+    }
+
+    // Inserts the batches that are full or waited long enough.
+    fn insert_due(&mut self) {
+        let interval = Duration::from_millis(self.batch.interval_ms);
+        let size = self.batch.size.max(1);
+        let due: Vec<usize> = (0..self.clauses.len())
+            .filter(|&index| {
+                let clause = &self.clauses[index];
+                clause.rows.len() >= size || clause.since.is_some_and(|since| since.elapsed() >= interval)
+            })
+            .collect();
+        self.insert(&due);
+    }
+}
+
+impl Sink for DatabaseSink {
+    fn accept(&mut self, received: &ReceivedEvent) {
+        // Lets check if the event matches any of the event_clauses, by name and where, if it does we buffer a row for its table.
+        for clause in &mut self.clauses {
+            if clause.matches(received) {
+                if let Some(row) = clause.row(received) {
+                    clause.rows.push(row);
+                    clause.since.get_or_insert_with(Instant::now);
+                }
+            }
+        }
+
+        self.insert_due();
+    }
+
+    fn flush(&mut self) {
+        // The spool is replayed even when no new rows come in.
+        self.replay();
+        self.insert_due();
+    }
+
+    fn shutdown(&mut self) {
+        let all: Vec<usize> = (0..self.clauses.len()).collect();
+        self.insert(&all);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use ami::{Headers, Packet};
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    use crate::settings::{Conversion, Spool, ValueType};
+
+    use super::*;
+
+    fn spooled_row(table: &str, n: i64, offset: u64) -> (SpooledRow, u64) {
+        let row = SpooledRow {
+            table: table.to_owned(),
+            columns: vec![String::from("n")],
+            values: vec![Value::Int(n)],
+        };
+        (row, offset)
+    }
+
+    #[test]
+    fn unspools_the_runs_of_a_table() {
+        let batches = unspool(vec![
+            spooled_row("calls", 1, 10),
+            spooled_row("calls", 2, 20),
+            spooled_row("queues", 3, 30),
+            spooled_row("calls", 4, 40),
+        ]);
+
+        // Only neighbouring rows are grouped, so the order of the spool is kept.
+        let batches: Vec<(&str, usize, Vec<u64>)> = batches.iter().map(|(rows, offsets)| (rows.table.as_str(), rows.rows.len(), offsets.clone())).collect();
+        assert_eq!(batches, vec![("calls", 2, vec![10, 20]), ("queues", 1, vec![30]), ("calls", 1, vec![40])]);
+    }
+
+    #[test]
+    fn inserts_the_rows_of_a_clause_into_sqlite() {
+        let directory = env::temp_dir().join(format!("sms-sqlite-{}", process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("events.db").to_string_lossy().into_owned();
+
+        let database = DatabaseConnection {
+            id: String::from("lite"),
+            driver: Driver::Sqlite,
+            host: String::new(),
+            port: 0,
+            database: path.clone(),
+            auto_schema: AutoSchema::Apply,
+            batch: Batch {
+                size: 1,
+                interval_ms: 0,
+            },
+            spool: Spool {
+                directory: directory.to_string_lossy().into_owned(),
+                max_bytes: 0,
+                ..Spool::default()
+            },
+            ..DatabaseConnection::default()
+        };
+        let event_clause = EventClause {
+            event_name: String::from("Hangup"),
+            db_connection_id: String::from("lite"),
+            db_table: String::from("hangups"),
+            event_data_link: [
+                (String::from("Channel"), DataLink::Column(String::from("channel"))),
+                (String::from("Duration"), DataLink::Converted(Conversion {
+                    column: String::from("duration"),
+                    value_type: ValueType::Duration,
+                    format: None,
+                })),
+                (String::from("%SERVER_NAME%"), DataLink::Column(String::from("pbx"))),
+            ].iter().cloned().collect(),
+            ..EventClause::default()
+        };
+
+        let pool = Pool::connect(&database).unwrap();
+        let mut sink = DatabaseSink::new(&database, pool, &[event_clause]).unwrap();
+
+        let mut headers = Headers::default();
+        headers.push(String::from("Event"), String::from("Hangup"));
+        headers.push(String::from("Channel"), String::from("SIP/100-00000001"));
+        headers.push(String::from("Duration"), String::from("1:30"));
+        let event = Packet {
+            headers,
+            rest: String::new(),
+        };
+        sink.accept(&ReceivedEvent {
+            server_name: "pbx1",
+            server_host: "10.0.0.1",
+            logger_host: "logger",
+            event: &event,
+            received_at: Utc.timestamp_opt(1634385600, 0).unwrap(),
+            uuid: Uuid::new_v4(),
+        });
+        sink.shutdown();
+
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        let row: (String, i64, String, String, String) = connection
+            .query_row("SELECT channel, duration, pbx, server, received_at FROM hangups", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+            })
+            .unwrap();
+        assert_eq!(row, (
+            String::from("SIP/100-00000001"),
+            90,
+            String::from("pbx1"),
+            String::from("pbx1"),
+            String::from("2021-10-16 12:00:00.000"),
+        ));
+
+        drop(connection);
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
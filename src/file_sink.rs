@@ -0,0 +1,106 @@
+use std::{collections::HashMap, fs, io, sync::mpsc::Sender};
+use chrono::Utc;
+use chrono_tz::Tz;
+
+use crate::{compression::start_compressor, format::Formatter, log_file::{LogFile, filename_template, uncompressed_files}, placeholder::ReceivedEvent, retention::start_janitor, settings::{FileSettings, Server}, sink::Sink};
+
+// Writes the events to log files in the target directory, or in a directory per server.
+pub struct FileSink {
+    settings: FileSettings,
+    formatter: Formatter,
+    timezone: Tz,
+    compressor: Option<Sender<String>>,
+    // The log files by directory, one per server or a single one for all of them depending on the settings.
+    files: HashMap<String, LogFile>,
+}
+
+impl FileSink {
+    // Creates the directories of the log files, and starts compressing and pruning them as the settings ask.
+    pub fn new(mut settings: FileSettings, servers: &[Server]) -> io::Result<Self> {
+        // Lets check if the file path end with a /.
+        // If it does lets remove it.
+        if settings.target_directory.ends_with('/') {
+            settings.target_directory.pop();
+        }
+
+        fs::create_dir_all(&settings.target_directory)?;
+
+        // With directory_per_server every server gets its own directory, lets create them now.
+        let directories = if settings.directory_per_server {
+            let mut directories = vec![];
+            for server in servers {
+                let dir = format!("{}/{}", &settings.target_directory, server.name);
+                println!("Creating directory {}", dir);
+                fs::create_dir_all(&dir)?;
+
+                directories.push(dir);
+            }
+            directories
+        } else {
+            vec![settings.target_directory.clone()]
+        };
+
+        // The files of a previous run were never closed, lets compress the ones of the periods that are over.
+        let compressor = start_compressor(settings.compression);
+        if let Some(compressor) = &compressor {
+            let template = filename_template(&settings);
+            let period = Utc::now().with_timezone(&settings.timezone()).format(template).to_string();
+            for directory in &directories {
+                match uncompressed_files(directory, template, &period) {
+                    Ok(files) => for file in files {
+                        let _ = compressor.send(file);
+                    },
+                    Err(e) => println!("Unable to list the log files of {} with error: {}", directory, e),
+                }
+            }
+        }
+
+        // Lets start pruning old log files in every directory we write to.
+        start_janitor(directories, filename_template(&settings).to_owned(), settings.timezone(), settings.retention.clone());
+
+        Ok(FileSink {
+            formatter: Formatter::new(&settings),
+            timezone: settings.timezone(),
+            compressor,
+            files: HashMap::new(),
+            settings,
+        })
+    }
+
+    fn directory(&self, server_name: &str) -> String {
+        if self.settings.directory_per_server {
+            format!("{}/{}", self.settings.target_directory, server_name)
+        } else {
+            self.settings.target_directory.clone()
+        }
+    }
+}
+
+impl Sink for FileSink {
+    fn accept(&mut self, received: &ReceivedEvent) {
+        // Now lets get the target file for the current server.
+        let directory = self.directory(received.server_name);
+        let formatter = &self.formatter;
+        let compressor = &self.compressor;
+        let file = self.files
+            .entry(directory.clone())
+            .or_insert_with(|| LogFile::new(directory, formatter.file_header(), compressor.clone()));
+
+        let time = received.received_at.with_timezone(&self.timezone);
+
+        let msg = self.formatter.record(received, &time);
+
+        // Lets write the message to the events file, the file takes care of rotating.
+        if let Err(e) = file.write(&self.settings, &time, msg.as_bytes()) {
+            println!("Unable to write event to log file with error: {}", e);
+        }
+    }
+
+    fn flush(&mut self) {
+        for file in self.files.values_mut() {
+            if let Err(e) = file.flush() {
+                println!("Unable to flush log file with error: {}", e);
+            }
+        }
+    }
+}
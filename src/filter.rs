@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use ami::Packet;
+use glob::Pattern;
+use regex::Regex;
+
+use crate::settings::{Filters, HeaderRule, Settings, SettingsError};
+
+// A header rule with its regex compiled.
+struct CompiledHeaderRule {
+    header: String,
+    regex: Regex,
+}
+
+impl CompiledHeaderRule {
+    fn new(rule: &HeaderRule) -> Result<Self, SettingsError> {
+        let regex = Regex::new(&rule.regex)
+            .map_err(|e| SettingsError::InvalidFilter(format!("regex {:?} for header {}: {}", rule.regex, rule.header, e)))?;
+
+        Ok(CompiledHeaderRule {
+            header: rule.header.clone(),
+            regex,
+        })
+    }
+
+    fn matches(&self, packet: &Packet) -> bool {
+        match packet.headers.lookup(&self.header) {
+            Some(value) => self.regex.is_match(value),
+            None => false,
+        }
+    }
+}
+
+// The compiled form of a Filters section from the settings.
+pub struct EventFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    include_headers: Vec<CompiledHeaderRule>,
+    exclude_headers: Vec<CompiledHeaderRule>,
+}
+
+fn compile_patterns(patterns: &[String]) -> Result<Vec<Pattern>, SettingsError> {
+    patterns
+        .iter()
+        .map(|pattern| Pattern::new(pattern).map_err(|e| SettingsError::InvalidFilter(format!("event pattern {:?}: {}", pattern, e))))
+        .collect()
+}
+
+fn compile_rules(rules: &[HeaderRule]) -> Result<Vec<CompiledHeaderRule>, SettingsError> {
+    rules.iter().map(CompiledHeaderRule::new).collect()
+}
+
+impl EventFilter {
+    pub fn new(filters: &Filters) -> Result<Self, SettingsError> {
+        Ok(EventFilter {
+            include: compile_patterns(&filters.include)?,
+            exclude: compile_patterns(&filters.exclude)?,
+            include_headers: compile_rules(&filters.include_headers)?,
+            exclude_headers: compile_rules(&filters.exclude_headers)?,
+        })
+    }
+
+    // Whether the event should be kept.
+    pub fn matches(&self, packet: &Packet) -> bool {
+        let event = packet.event_name().unwrap_or("");
+
+        let included = (self.include.is_empty() && self.include_headers.is_empty())
+            || self.include.iter().any(|pattern| pattern.matches(event))
+            || self.include_headers.iter().any(|rule| rule.matches(packet));
+
+        let excluded = self.exclude.iter().any(|pattern| pattern.matches(event))
+            || self.exclude_headers.iter().any(|rule| rule.matches(packet));
+
+        included && !excluded
+    }
+}
+
+// The filters of the servers from the settings, the one that applies to an event unless the sink has its own.
+pub struct FilterSet {
+    global: EventFilter,
+    servers: HashMap<String, EventFilter>,
+}
+
+impl FilterSet {
+    pub fn new(settings: &Settings) -> Result<Self, SettingsError> {
+        let mut servers = HashMap::new();
+        for server in &settings.servers {
+            if let Some(filters) = &server.filters {
+                servers.insert(server.name.clone(), EventFilter::new(filters)?);
+            }
+        }
+
+        Ok(FilterSet {
+            global: EventFilter::new(&settings.filters)?,
+            servers,
+        })
+    }
+
+    // The filter for events of the server going to a sink, the filter of the sink itself replaces it.
+    pub fn for_sink<'a>(&'a self, sink_filter: Option<&'a EventFilter>, server_name: &str) -> &'a EventFilter {
+        sink_filter.unwrap_or_else(|| self.servers.get(server_name).unwrap_or(&self.global))
+    }
+}
+
+// Compiles the filters of a sink, if it has any.
+pub fn sink_filter(filters: &Option<Filters>) -> Result<Option<EventFilter>, SettingsError> {
+    match filters {
+        Some(filters) => Ok(Some(EventFilter::new(filters)?)),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,104 @@
+use std::fmt::Display;
+use ami::Packet;
+use chrono::{DateTime, SecondsFormat, TimeZone};
+use serde::Serialize;
+
+use crate::{placeholder::ReceivedEvent, settings::{FileSettings, Format}};
+
+// A jsonl record, the packet headers and rest sit next to the server, timestamp and uuid.
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    server: &'a str,
+    timestamp: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time: Option<String>,
+    uuid: String,
+    #[serde(flatten)]
+    packet: &'a Packet,
+}
+
+// Quotes a csv field when it needs it, doubling the quotes inside.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    }
+    else {
+        value.to_owned()
+    }
+}
+
+fn csv_row<'a>(fields: impl Iterator<Item = &'a str>) -> String {
+    let fields: Vec<String> = fields.map(csv_field).collect();
+    format!("{}\r\n", fields.join(","))
+}
+
+// Turns the events into the records written to the log files, in the format from the settings.
+pub struct Formatter {
+    format: Format,
+    iso_timestamp: bool,
+    csv_columns: Vec<String>,
+}
+
+impl Formatter {
+    pub fn new(settings: &FileSettings) -> Self {
+        Formatter {
+            format: settings.format,
+            iso_timestamp: settings.iso_timestamp,
+            csv_columns: settings.csv_columns.clone(),
+        }
+    }
+
+    // What goes at the top of every new log file, the column names for csv and nothing for the others.
+    pub fn file_header(&self) -> Vec<u8> {
+        match self.format {
+            Format::Csv => csv_row(self.csv_columns.iter().map(|column| column.as_str())).into_bytes(),
+            _ => vec![],
+        }
+    }
+
+    // The time is the receive time of the event, in the timezone of the log files.
+    pub fn record<Tz: TimeZone>(&self, received: &ReceivedEvent, time: &DateTime<Tz>) -> String
+    where Tz::Offset: Display {
+        let server_name = received.server_name;
+        let packet = received.event;
+        let iso = || time.to_rfc3339_opts(SecondsFormat::Millis, false);
+
+        match self.format {
+            Format::Legacy => {
+                // With iso_timestamp the millis are followed by the same time in the settings timezone, like "2021-10-16T09:30:00.000-03:00".
+                let timestamp = if self.iso_timestamp {
+                    format!("{}::{}", time.timestamp_millis(), iso())
+                } else {
+                    time.timestamp_millis().to_string()
+                };
+
+                format!("{}::{}::{}\r\n", server_name, timestamp, serde_json::to_string(packet).unwrap())
+            },
+            Format::Jsonl => {
+                let record = JsonRecord {
+                    server: server_name,
+                    timestamp: time.timestamp_millis(),
+                    time: if self.iso_timestamp { Some(iso()) } else { None },
+                    uuid: received.uuid.to_string(),
+                    packet,
+                };
+
+                format!("{}\n", serde_json::to_string(&record).unwrap())
+            },
+            Format::Csv => {
+                let fields: Vec<String> = self.csv_columns
+                    .iter()
+                    .map(|column| match column.as_str() {
+                        "%TIMESTAMP%" => time.timestamp_millis().to_string(),
+                        "%TIME%" => iso(),
+                        key => received.text(key).unwrap_or_default(),
+                    })
+                    .collect();
+                csv_row(fields.iter().map(String::as_str))
+            },
+            Format::Raw => {
+                format!("### {} {}\r\n{}", server_name, time.timestamp_millis(), packet.to_wire())
+            },
+        }
+    }
+}
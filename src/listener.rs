@@ -0,0 +1,253 @@
+use std::{fmt::{self, Display}, sync::mpsc::Sender, thread, time::{Duration, Instant}};
+use ami::{ActionHandle, AmiClient, AmiError, AmiMessage, AmiVersion, ConnectOptions, Headers, Packet, TlsOptions};
+use rand::Rng;
+
+use crate::settings;
+
+// How long a single read blocks before the listener gets a chance to check on its keepalive ping.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+pub enum ListenerError {
+    AmiError(AmiError),
+    UnsupportedVersion(AmiVersion),
+    PingTimeout,
+}
+
+impl Display for ListenerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ListenerError::AmiError(e) => {
+                write!(f, "{}", e)
+            },
+            ListenerError::UnsupportedVersion(version) => {
+                write!(f, "AMI protocol version {} is not allowed by the settings.", version)
+            },
+            ListenerError::PingTimeout => {
+                write!(f, "No reply to keepalive ping, connection is dead.")
+            },
+        }
+    }
+}
+
+impl ListenerError {
+    // Errors that will not go away by reconnecting, there is no point in retrying after them.
+    fn is_fatal(&self) -> bool {
+        match self {
+            ListenerError::AmiError(e) => e.is_fatal(),
+            ListenerError::UnsupportedVersion(_) => true,
+            ListenerError::PingTimeout => false,
+        }
+    }
+}
+
+impl From<AmiError> for ListenerError {
+    fn from(e: AmiError) -> Self {
+        ListenerError::AmiError(e)
+    }
+}
+
+// Builds an event that did not come from the AMI server, but from the logger itself, e.g. to mark a disconnect in the event log.
+pub fn marker(event: &str, fields: &[(&str, String)]) -> Packet {
+    let mut headers = Headers::default();
+    headers.push(String::from("Event"), event.to_owned());
+    for (name, value) in fields {
+        headers.push(name.to_string(), value.clone());
+    }
+
+    Packet {
+        headers,
+        rest: String::from(""),
+    }
+}
+
+fn connect_options(server: &settings::Server) -> ConnectOptions {
+    let tls = if server.tls {
+        Some(TlsOptions {
+            ca_file: server.ca_file.clone(),
+            client_cert: server.client_cert.clone(),
+            client_key: server.client_key.clone(),
+            verify_hostname: server.verify_hostname,
+        })
+    }
+    else {
+        None
+    };
+
+    ConnectOptions {
+        host: server.host.clone(),
+        port: server.port,
+        tls,
+        // Nothing should block for longer than the keepalive timeout.
+        timeout: Duration::from_millis(server.keepalive.timeout_ms),
+        poll_interval: POLL_INTERVAL,
+        action_id_prefix: format!("logger-{}", server.name),
+    }
+}
+
+// Checks the protocol version announced by the server against the allow-list and minimum version in the settings.
+fn check_version(server: &settings::Server, version: &AmiVersion) -> bool {
+    if let Some(min_version) = &server.min_version {
+        if version < min_version {
+            return false;
+        }
+    }
+
+    server.allowed_versions.is_empty() || server.allowed_versions.iter().any(|allowed| version.matches(allowed))
+}
+
+// Opens the connection to the AMI server and logs in.
+fn connect(server: &settings::Server) -> Result<AmiClient, ListenerError> {
+    let mut client = AmiClient::connect(&connect_options(server))?;
+
+    if !check_version(server, client.version()) {
+        return Err(ListenerError::UnsupportedVersion(client.version().clone()));
+    }
+
+    client.login(&server.username, &server.password, server.auth, server.events.as_deref())?;
+
+    // Lets ask the server to filter its events before sending them.
+    // A rejected filter only costs us bandwidth, so we keep going without it.
+    for filter in &server.ami_filters {
+        match client.add_filter(filter) {
+            Ok(()) => {},
+            Err(e @ AmiError::FilterRejected(_, _)) => {
+                println!("Warning: {} on server {}.", e, server.name);
+            },
+            Err(e) => {
+                return Err(e.into());
+            }
+        }
+    }
+
+    Ok(client)
+}
+
+// Calculates how long to wait before the given reconnection attempt, attempts start at 1.
+fn backoff_delay(reconnect: &settings::Reconnect, attempt: u32) -> Duration {
+    let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+    let delay = (reconnect.initial_delay_ms as f64 * reconnect.multiplier.powi(exponent))
+        .min(reconnect.max_delay_ms as f64);
+
+    let jitter = delay * reconnect.jitter.clamp(0.0, 1.0);
+    let jitter = rand::thread_rng().gen_range(0.0..=jitter);
+
+    Duration::from_millis((delay + jitter) as u64)
+}
+
+// Reads events from a logged in client and sends them to the main thread, while keeping the connection alive with pings.
+// Returns the error that ended the session, or None if the main thread stopped listening.
+fn receive_events(server: &settings::Server, client: &mut AmiClient, sender: &Sender<(String, Packet)>) -> Option<ListenerError> {
+    let keepalive = &server.keepalive;
+    let interval = Duration::from_millis(keepalive.interval_ms);
+    let timeout = Duration::from_millis(keepalive.timeout_ms);
+
+    let mut next_ping = Instant::now() + interval;
+    // The ping we are waiting a reply for, and until when we wait.
+    // The reply goes to its handle instead of being received here, so it never ends up in the event log.
+    let mut pending_ping: Option<(ActionHandle, Instant)> = None;
+
+    loop {
+        match client.recv() {
+            Ok(Some(AmiMessage::Event(packet))) => {
+                // If it is an event we will send it to the main thread to be logged.
+                if sender.send((server.name.clone(), packet)).is_err() {
+                    return None;
+                }
+            },
+            Ok(_) => {},
+            Err(e) => {
+                return Some(e.into());
+            }
+        }
+
+        if keepalive.interval_ms == 0 {
+            continue;
+        }
+
+        let now = Instant::now();
+        match &pending_ping {
+            Some((ping, deadline)) => {
+                // Any reply proves the connection is alive.
+                if ping.try_result().is_some() {
+                    pending_ping = None;
+                }
+                else if now >= *deadline {
+                    return Some(ListenerError::PingTimeout);
+                }
+            },
+            None => {
+                if now >= next_ping {
+                    match client.send(&[("Action", "Ping")]) {
+                        Ok(ping) => {
+                            pending_ping = Some((ping, now + timeout));
+                        },
+                        Err(e) => {
+                            return Some(e.into());
+                        }
+                    }
+                    next_ping = now + interval;
+                }
+            }
+        }
+    }
+}
+
+// Keeps a connection to the server and sends all its events to the main thread, reconnecting with backoff when it drops.
+pub fn listener(server: settings::Server, sender: Sender<(String, Packet)>) {
+    // How many connection attempts failed in a row, 0 means we are not reconnecting.
+    let mut attempt: u32 = 0;
+    // Whether we were logged in at least once, so we know the next successful login is a reconnect.
+    let mut was_connected = false;
+
+    loop {
+        if attempt > 0 {
+            let delay = backoff_delay(&server.reconnect, attempt);
+            println!("Reconnecting to server {} in {}ms (attempt {}).", server.name, delay.as_millis(), attempt);
+            thread::sleep(delay);
+        }
+
+        let mut client = match connect(&server) {
+            Ok(client) => client,
+            Err(e) if e.is_fatal() => {
+                println!("Giving up on server {}, {}:{}, with error: {}", server.name, server.host, server.port, e);
+                return;
+            },
+            Err(e) => {
+                println!("Unable to connect to server {}, {}:{}, with error: {}", server.name, server.host, server.port, e);
+                attempt += 1;
+                continue;
+            }
+        };
+
+        println!("Connected to server {} (AMI {}).", server.name, client.version());
+
+        if was_connected {
+            let marker = marker("LoggerReconnect", &[
+                ("Attempts", attempt.to_string()),
+                ("ProtocolVersion", client.version().to_string()),
+            ]);
+            if sender.send((server.name.clone(), marker)).is_err() {
+                return;
+            }
+        }
+        was_connected = true;
+
+        let error = match receive_events(&server, &mut client, &sender) {
+            Some(error) => error,
+            // The main thread is gone, nobody is listening anymore.
+            None => return,
+        };
+
+        println!("Lost connection to server {}, with error: {}", server.name, error);
+
+        let marker = marker("LoggerDisconnect", &[
+            ("Reason", error.to_string()),
+        ]);
+        if sender.send((server.name.clone(), marker)).is_err() {
+            return;
+        }
+
+        attempt = 1;
+    }
+}
@@ -0,0 +1,210 @@
+use std::{fmt::Display, fs::{self, File, OpenOptions}, io::{self, Write}, sync::mpsc::Sender};
+use chrono::{DateTime, TimeZone};
+use chrono::format::{Item, StrftimeItems};
+use glob::Pattern;
+
+use crate::{compression, settings::{FileSettings, RotationInterval, SettingsError}};
+
+// The file name template used when the settings dont have one.
+// Without a timezone the %Z keeps the names we always had, like "events_2021-10-16UTC.log".
+// With one it is left out, a timezone with daylight saving time changes its abbreviation (EST to EDT) in the middle of a day.
+pub fn filename_template(settings: &FileSettings) -> &str {
+    match (&settings.rotation.filename_template, settings.rotation.interval, &settings.timezone) {
+        (Some(template), _, _) => template,
+        (None, RotationInterval::Daily, None) => "events_%Y-%m-%d%Z.log",
+        (None, RotationInterval::Hourly, None) => "events_%Y-%m-%d_%H%Z.log",
+        (None, RotationInterval::Daily, Some(_)) => "events_%Y-%m-%d.log",
+        (None, RotationInterval::Hourly, Some(_)) => "events_%Y-%m-%d_%H.log",
+    }
+}
+
+// Checks the template is a valid strftime format that results in a plain file name.
+pub fn validate_template(template: &str) -> Result<(), SettingsError> {
+    if StrftimeItems::new(template).any(|item| matches!(item, Item::Error)) {
+        return Err(SettingsError::InvalidFilenameTemplate(format!("{:?} is not a valid strftime format", template)));
+    }
+    if template.contains('/') || template.contains('\\') {
+        return Err(SettingsError::InvalidFilenameTemplate(format!("{:?} can not contain a path separator", template)));
+    }
+    Ok(())
+}
+
+// A glob matching every file name the template can produce, including the size rotated ones.
+// Every strftime field becomes a "*", so "events_%Y-%m-%d%Z.log" gives "events_*.log", and one more "*" before the
+// extension takes the sequence number.
+pub fn filename_pattern(template: &str) -> Pattern {
+    let mut pattern = String::new();
+    for item in StrftimeItems::new(template) {
+        match item {
+            Item::Literal(text) | Item::Space(text) => pattern.push_str(&Pattern::escape(text)),
+            Item::OwnedLiteral(text) | Item::OwnedSpace(text) => pattern.push_str(&Pattern::escape(&text)),
+            _ => pattern.push('*'),
+        }
+    }
+
+    if let Some(dot) = pattern.rfind('.') {
+        pattern.insert(dot, '*');
+    }
+
+    // Neighbouring fields would make a "**", which glob only allows as a whole path component.
+    while pattern.contains("**") {
+        pattern = pattern.replace("**", "*");
+    }
+
+    // Every part of the pattern is either escaped or a "*", so it is always valid.
+    Pattern::new(&pattern).unwrap()
+}
+
+// Adds the sequence number of a size rotated file before the extension, "events_2021-10-16UTC.log" becomes "events_2021-10-16UTC.1.log".
+fn with_sequence(name: &str, sequence: u32) -> String {
+    if sequence == 0 {
+        return name.to_owned();
+    }
+
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => format!("{}.{}{}", &name[..dot], sequence, &name[dot..]),
+        _ => format!("{}.{}", name, sequence),
+    }
+}
+
+// Whether the file name is the one of the period, with or without a sequence, the reverse of with_sequence.
+pub fn is_of_period(name: &str, period: &str) -> bool {
+    if name == period {
+        return true;
+    }
+
+    let (stem, extension) = match period.rfind('.') {
+        Some(dot) if dot > 0 => period.split_at(dot),
+        _ => (period, ""),
+    };
+    name.strip_prefix(stem)
+        .and_then(|name| name.strip_prefix('.'))
+        .and_then(|name| name.strip_suffix(extension))
+        .is_some_and(|sequence| !sequence.is_empty() && sequence.chars().all(|c| c.is_ascii_digit()))
+}
+
+// The log files in the directory that are not compressed yet, leaving out the ones of the current period.
+// Those were left behind by a previous run, which stopped before it could close them.
+pub fn uncompressed_files(directory: &str, template: &str, period: &str) -> io::Result<Vec<String>> {
+    let pattern = filename_pattern(template);
+    let mut files = vec![];
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => continue,
+        };
+
+        // A template without an extension matches the compressed files and their temporary files too.
+        if compression::strip_extension(name) != name || name.ends_with(".tmp") {
+            continue;
+        }
+        if !pattern.matches(name) || is_of_period(name, period) || !entry.metadata()?.is_file() {
+            continue;
+        }
+        files.push(format!("{}/{}", directory, name));
+    }
+    files.sort();
+    Ok(files)
+}
+
+// An event log file in a directory, that rotates to a new file when the period in its name changes or it gets too big.
+// Every message is written whole to a single file, a rotation only ever happens between two messages.
+// The files it is done with are handed to the compressor, if there is one.
+pub struct LogFile {
+    directory: String,
+    // Written at the start of every new file, like the column names of a csv.
+    header: Vec<u8>,
+    compressor: Option<Sender<String>>,
+    file: Option<File>,
+    // The file name for the current period, before adding the sequence.
+    name: String,
+    sequence: u32,
+    size: u64,
+}
+
+impl LogFile {
+    pub fn new(directory: String, header: Vec<u8>, compressor: Option<Sender<String>>) -> Self {
+        LogFile {
+            directory,
+            header,
+            compressor,
+            file: None,
+            name: String::new(),
+            sequence: 0,
+            size: 0,
+        }
+    }
+
+    fn path(&self) -> String {
+        format!("{}/{}", self.directory, with_sequence(&self.name, self.sequence))
+    }
+
+    // Closes the current file, if any, and hands it to the compressor.
+    fn close(&mut self) {
+        if self.file.take().is_some() {
+            if let Some(compressor) = &self.compressor {
+                // The compressor only stops with the process, if it is gone the file just stays uncompressed.
+                let _ = compressor.send(self.path());
+            }
+        }
+    }
+
+    // Opens the file for the current name and sequence, picking up where we left off if it already exists.
+    fn open(&mut self) -> io::Result<()> {
+        let path = self.path();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+
+        self.size = file.metadata()?.len();
+        if self.size == 0 && !self.header.is_empty() {
+            file.write_all(&self.header)?;
+            self.size = self.header.len() as u64;
+        }
+        self.file = Some(file);
+        Ok(())
+    }
+
+    // Writes the message, rotating first if needed.
+    pub fn write<Tz: TimeZone>(&mut self, settings: &FileSettings, now: &DateTime<Tz>, msg: &[u8]) -> io::Result<()>
+    where Tz::Offset: Display {
+        let rotation = &settings.rotation;
+        let name = now.format(filename_template(settings)).to_string();
+        // A file with nothing but the header always takes the message, otherwise a big message would rotate forever.
+        let header = self.header.len() as u64;
+        let full = |size: u64| rotation.max_size_bytes > 0 && size > header && size + msg.len() as u64 > rotation.max_size_bytes;
+
+        if self.file.is_none() || name != self.name {
+            self.close();
+
+            // New period, lets skip the files of this period that are already full or compressed from a previous run.
+            self.name = name;
+            self.sequence = 0;
+            while compression::is_compressed(&self.path()) || full(fs::metadata(self.path()).map(|metadata| metadata.len()).unwrap_or(0)) {
+                self.sequence += 1;
+            }
+            self.open()?;
+        }
+        else if full(self.size) {
+            self.close();
+            self.sequence += 1;
+            self.open()?;
+        }
+
+        if let Some(file) = &mut self.file {
+            file.write_all(msg)?;
+            self.size += msg.len() as u64;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
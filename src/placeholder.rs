@@ -0,0 +1,68 @@
+use ami::Packet;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::{conversion::DATETIME_FORMAT, database::Value, settings::ValueType};
+
+// The keys of event_data_link that are filled in by the logger instead of a header of the event:
+// - %SERVER_NAME%, %SERVER_HOST%: the name and host of the server the event came from, as in the settings.
+// - %RECEIVED_AT%: when the event came in, in UTC. %RECEIVED_AT_MS% is the same as milliseconds since 1970.
+// - %EVENT_NAME%: the Event header.
+// - %RAW_JSON%: the whole event as JSON, like the legacy log file record.
+// - %LOGGER_HOST%: the host name of the machine the logger runs on.
+// - %EVENT_UUID%: a random UUID for the event.
+// A header with the same name as the key always wins.
+pub struct ReceivedEvent<'a> {
+    pub server_name: &'a str,
+    pub server_host: &'a str,
+    pub logger_host: &'a str,
+    pub event: &'a Packet,
+    pub received_at: DateTime<Utc>,
+    // Stamped once by the dispatcher, every sink and clause the event goes to gets the same time and uuid.
+    pub uuid: Uuid,
+}
+
+impl ReceivedEvent<'_> {
+    pub fn placeholder(&self, key: &str) -> Option<Value> {
+        let value = match key {
+            "%SERVER_NAME%" => Value::Text(self.server_name.to_owned()),
+            "%SERVER_HOST%" => Value::Text(self.server_host.to_owned()),
+            "%RECEIVED_AT%" => Value::Text(self.received_at.format(DATETIME_FORMAT).to_string()),
+            "%RECEIVED_AT_MS%" => Value::Int(self.received_at.timestamp_millis()),
+            "%EVENT_NAME%" => match self.event.event_name() {
+                Some(event_name) => Value::Text(event_name.to_owned()),
+                None => Value::Null,
+            },
+            "%RAW_JSON%" => Value::Text(serde_json::to_string(self.event).unwrap()),
+            "%LOGGER_HOST%" => Value::Text(self.logger_host.to_owned()),
+            "%EVENT_UUID%" => Value::Text(self.uuid.to_string()),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    // The value of a header or placeholder as text, for the where of the event clauses.
+    pub fn text(&self, key: &str) -> Option<String> {
+        if let Some(value) = self.event.headers.lookup(key) {
+            return Some(value.to_owned());
+        }
+
+        match self.placeholder(key)? {
+            Value::Null => None,
+            Value::Text(text) => Some(text),
+            Value::Int(int) => Some(int.to_string()),
+            Value::Float(float) => Some(float.to_string()),
+            Value::Bool(bool) => Some(bool.to_string()),
+            Value::Bytes(bytes) => Some(String::from_utf8_lossy(&bytes).into_owned()),
+        }
+    }
+}
+
+// The type of the values of a placeholder, for the columns auto_schema creates.
+pub fn placeholder_type(key: &str) -> ValueType {
+    match key {
+        "%RECEIVED_AT%" => ValueType::Datetime,
+        "%RECEIVED_AT_MS%" => ValueType::Int,
+        _ => ValueType::Text,
+    }
+}
@@ -0,0 +1,99 @@
+use std::{fs, io, path::PathBuf, thread, time::{Duration, SystemTime}};
+use chrono::Utc;
+use chrono_tz::Tz;
+use glob::Pattern;
+
+use crate::{compression, log_file::{filename_pattern, is_of_period}, settings::Retention};
+
+// A log file found in one of the log directories.
+struct StoredFile {
+    name: String,
+    path: PathBuf,
+    modified: SystemTime,
+    size: u64,
+}
+
+// Lists the log files in the directory, compressed or not, oldest first.
+fn list_files(directory: &str, pattern: &Pattern) -> io::Result<Vec<StoredFile>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => continue,
+        };
+        if !pattern.matches(compression::strip_extension(name)) {
+            continue;
+        }
+
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+
+        files.push(StoredFile {
+            name: name.to_owned(),
+            path: entry.path(),
+            modified: metadata.modified()?,
+            size: metadata.len(),
+        });
+    }
+
+    files.sort_by_key(|file| file.modified);
+    Ok(files)
+}
+
+// Removes the files of the directory that are past the retention limits.
+// The files of the period are the ones being written, those always stay. Going by the newest one would not work,
+// the compressed file of the period that just ended is written after the new file was created.
+fn prune(directory: &str, pattern: &Pattern, period: &str, retention: &Retention) -> io::Result<()> {
+    let mut files = list_files(directory, pattern)?;
+    files.retain(|file| !is_of_period(&file.name, period));
+
+    let mut total: u64 = files.iter().map(|file| file.size).sum();
+    let max_age = Duration::from_secs(u64::from(retention.retention_days) * 24 * 60 * 60);
+    let now = SystemTime::now();
+
+    for file in files {
+        let expired = retention.retention_days > 0 && now.duration_since(file.modified).unwrap_or_default() > max_age;
+        let over_size = retention.max_total_bytes > 0 && total > retention.max_total_bytes;
+        if !expired && !over_size {
+            // The files are sorted oldest first, so the rest are newer and fit too.
+            break;
+        }
+
+        match fs::remove_file(&file.path) {
+            Ok(()) => {
+                println!("Removed old log file {} ({} bytes).", file.path.display(), file.size);
+                total -= file.size;
+            },
+            Err(e) => println!("Unable to remove old log file {} with error: {}", file.path.display(), e),
+        }
+    }
+
+    Ok(())
+}
+
+// Starts the thread that prunes the log files in the directories every check_interval_secs.
+// The size limit applies to each directory on its own, so with directory_per_server every server gets max_total_bytes.
+// Does nothing when neither retention_days nor max_total_bytes is set.
+pub fn start_janitor(directories: Vec<String>, template: String, timezone: Tz, retention: Retention) {
+    if retention.retention_days == 0 && retention.max_total_bytes == 0 {
+        return;
+    }
+
+    thread::spawn(move || {
+        let pattern = filename_pattern(&template);
+        let interval = Duration::from_secs(retention.check_interval_secs.max(1));
+        loop {
+            let period = Utc::now().with_timezone(&timezone).format(&template).to_string();
+            for directory in &directories {
+                if let Err(e) = prune(directory, &pattern, &period, &retention) {
+                    println!("Unable to prune log directory {} with error: {}", directory, e);
+                }
+            }
+            thread::sleep(interval);
+        }
+    });
+}
@@ -1,13 +1,33 @@
 use serde::{Deserialize, Serialize};
+use ami::{AmiVersion, AuthType};
+use chrono::format::{Item, StrftimeItems};
+use chrono_tz::Tz;
+use regex::Regex;
+use crate::{condition::Condition, log_file};
 use std::{collections::HashMap, error::Error, fmt::Display, fmt, fs::OpenOptions, io::{Read, Write}, path::Path};
 
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum SettingsError {
     ParseError(String),
     WriteParseError(String),
     WriteError,
     ReadError,
+    InvalidFilter(String),
+    InvalidFilenameTemplate(String),
+    InvalidTimezone(String),
+    UnknownDatabase(String),
+    EmptyTargetDirectory,
+    InvalidIdentifier(String),
+    InvalidConversion(String),
+    InvalidCondition(String),
+    InvalidKeepalive(String),
+    DuplicateDatabase(String),
+    DuplicateSink(String),
+    InvalidConnection(String),
+    UnknownConnection(String, String),
+    InvalidReconnect(String),
 }
 
 impl Error for SettingsError {}
@@ -27,22 +47,191 @@ impl Display for SettingsError {
             SettingsError::ReadError => {
                 write!(f, "Unable to read from settings file.")
             },
+            SettingsError::InvalidFilter(msg) => {
+                write!(f, "Invalid filter in settings file: {}", msg)
+            },
+            SettingsError::InvalidFilenameTemplate(msg) => {
+                write!(f, "Invalid filename_template in settings file: {}", msg)
+            },
+            SettingsError::InvalidTimezone(msg) => {
+                write!(f, "Invalid timezone in settings file: {}", msg)
+            },
+            SettingsError::UnknownDatabase(id) => {
+                write!(f, "Sink in settings file uses database {:?}, which is not in the databases.", id)
+            },
+            SettingsError::EmptyTargetDirectory => {
+                write!(f, "No target directory specified.")
+            },
+            SettingsError::InvalidIdentifier(msg) => {
+                write!(f, "Invalid event clause in settings file: {}", msg)
+            },
+            SettingsError::InvalidConversion(msg) => {
+                write!(f, "Invalid event_data_link conversion in settings file: {}", msg)
+            },
+            SettingsError::InvalidCondition(msg) => {
+                write!(f, "Invalid event clause where in settings file: {}", msg)
+            },
+            SettingsError::InvalidKeepalive(name) => {
+                write!(f, "Invalid keepalive for server {} in settings file: timeout_ms has to be more than 0.", name)
+            },
+            SettingsError::DuplicateDatabase(id) => {
+                write!(f, "Database {:?} is in the databases of the settings file more than once.", id)
+            },
+            SettingsError::DuplicateSink(id) => {
+                write!(f, "Database {:?} is in the sinks of the settings file more than once.", id)
+            },
+            SettingsError::InvalidConnection(msg) => {
+                write!(f, "Invalid database connection in settings file: {}", msg)
+            },
+            SettingsError::UnknownConnection(event_name, id) => {
+                write!(f, "Event clause for {} in settings file uses db_connection_id {:?}, which is not in the databases.", event_name, id)
+            },
+            SettingsError::InvalidReconnect(msg) => {
+                write!(f, "Invalid reconnect in settings file: {}", msg)
+            },
         }
     }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Settings {
-    pub basic: Basic,
+    // The log files, when no sinks are declared.
+    pub basic: FileSettings,
+    #[serde(default)]
+    pub filters: Filters,
     pub servers: Vec<Server>,
     pub databases: Vec<DatabaseConnection>,
     pub event_clauses: Vec<EventClause>,
+    // Where the events go. Leaving it out keeps the old setup: the log files from basic, and every database.
+    #[serde(default)]
+    pub sinks: Option<Vec<SinkSettings>>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Basic {
+// An output for the events, the type picks which one:
+// - [[sinks]] type = "file", with the same options as basic.
+// - [[sinks]] type = "database", database = "<id>", writes the event_clauses of that database connection.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SinkSettings {
+    File(Box<FileSettings>),
+    Database(DatabaseSinkSettings),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DatabaseSinkSettings {
+    pub database: String,
+}
+
+// The options of a set of log files.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileSettings {
     pub target_directory: String,
-    pub directory_per_server: bool
+    pub directory_per_server: bool,
+    // The IANA timezone of the log files, e.g. "America/Sao_Paulo". It decides when a new day (or hour) starts
+    // for the file names, leaving it out keeps UTC.
+    #[serde(default)]
+    pub timezone: Option<String>,
+    // Adds a human readable ISO-8601 timestamp with the offset of the timezone to each record,
+    // after the millis in the legacy format and as a "time" field in jsonl.
+    #[serde(default)]
+    pub iso_timestamp: bool,
+    // How the records are written to the log files, see Format.
+    #[serde(default)]
+    pub format: Format,
+    // The columns of the csv format, header names using the event_data_link addressing,
+    // "%TIMESTAMP%" (millis), "%TIME%" (ISO-8601) or the placeholders of event_data_link, like "%EVENT_UUID%".
+    #[serde(default = "default_csv_columns")]
+    pub csv_columns: Vec<String>,
+    // Compresses the log files once they are rotated, "none", "gzip" or "zstd".
+    #[serde(default)]
+    pub compression: Compression,
+    #[serde(default)]
+    pub rotation: Rotation,
+    #[serde(default)]
+    pub retention: Retention,
+    // Replaces the filters for the events written to the log files.
+    #[serde(default)]
+    pub filters: Option<Filters>,
+}
+
+// The format of the records in the log files:
+// - legacy: "server::millis::{json}\r\n", what the logger always wrote.
+// - jsonl: one JSON object per line with the server, timestamp and uuid as fields, for ingestion.
+// - csv: the csv_columns of each event, with a header row at the top of every file.
+// - raw: the event as the AMI server sent it, after a "### server millis" line, for forensic replay.
+// The timestamps are the same %RECEIVED_AT% the databases get, the uuid the same %EVENT_UUID%, legacy and raw leave it out.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Legacy,
+    Jsonl,
+    Csv,
+    Raw,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+// When the log files move on to a new file.
+// The file name comes from filename_template, a strftime format, every time the formatted name changes a new file is started.
+// Without a template the interval picks one: "events_%Y-%m-%d%Z.log" for daily or "events_%Y-%m-%d_%H%Z.log" for hourly,
+// without the %Z when a timezone is set.
+// With max_size_bytes set, a file that would grow past it is continued in "<name>.1.log", "<name>.2.log" and so on.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Rotation {
+    pub interval: RotationInterval,
+    pub max_size_bytes: u64,
+    pub filename_template: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RotationInterval {
+    #[default]
+    Daily,
+    Hourly,
+}
+
+// How long the log files are kept, checked every check_interval_secs for each log directory.
+// Files older than retention_days are removed, and then the oldest ones until the directory fits in max_total_bytes.
+// A 0 disables either limit, the files of the current period are never removed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Retention {
+    pub retention_days: u32,
+    pub max_total_bytes: u64,
+    pub check_interval_secs: u64,
+}
+
+// Decides which events are kept before they reach the log files and the databases.
+// An event is kept if it matches any include rule (or there are no include rules at all), and no exclude rule.
+// The [filters] section applies to every server, a server can replace it with its own filters, and so can the
+// log files (the filters of a file sink) and each database connection. The most specific one wins: sink, then server, then [filters].
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Filters {
+    // Event names, glob patterns like "RTCP*" are allowed.
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    // Regexes on header values, e.g. { header = "Channel", regex = "^Local/" }.
+    pub include_headers: Vec<HeaderRule>,
+    pub exclude_headers: Vec<HeaderRule>,
+}
+
+// Matches events where the header has a value matching the regex.
+// The header can use the same addressing as event_data_link, e.g. "ChanVariable(FOO)".
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HeaderRule {
+    pub header: String,
+    pub regex: String,
 }
 
 const SETTINGS_FILE: &str = "settings.toml";
@@ -56,6 +245,7 @@ impl Settings {
         let settings_file = Path::new(SETTINGS_FILE);
         if !settings_file.exists() {
             let settings = Settings::default();
+            settings.validate()?;
 
             // Lets convert the settings to a toml string and write it to the file.
             let toml = match toml::to_string(&settings) {
@@ -69,6 +259,7 @@ impl Settings {
             let mut f = match OpenOptions::new()
                 .write(true)
                 .create(true)
+                .truncate(true)
                 .open(SETTINGS_FILE) {
                     Ok(f) => f,
                     Err(_e) => {
@@ -78,13 +269,9 @@ impl Settings {
             
             // Lets write our toml string to the file.
             match write!(f, "{}", toml) {
-                Ok(_) => {
-                    return Ok(settings);
-                },
-                Err(_e) => {
-                    return Err(SettingsError::WriteError);
-                }
-            };
+                Ok(_) => Ok(settings),
+                Err(_e) => Err(SettingsError::WriteError),
+            }
         }
         else {
             // Lets read the settings file.
@@ -100,17 +287,116 @@ impl Settings {
             // Lets parse the settings file.
             let mut toml = String::from("");
             let _size = f.read_to_string(&mut toml);
-            let settings:Settings = match toml::from_str(&mut toml) {
+            let settings:Settings = match toml::from_str(&toml) {
                 Ok(settings) => settings,
                 Err(e) => {
                     return Err(SettingsError::ParseError(e.to_string()));
                 }
             };
+            settings.validate()?;
             Ok(settings)
         }
     }
 }
 
+impl Settings {
+    // Checks the values serde can not check by itself.
+    // Init runs it on the defaults and the settings file alike, so the rest of the logger can take what it checks
+    // for granted, and expect() it where it has to.
+    fn validate(&self) -> Result<(), SettingsError> {
+        // The keepalive timeout is also the connect and login timeout, a socket can not wait for 0ms.
+        for server in &self.servers {
+            if server.keepalive.timeout_ms == 0 {
+                return Err(SettingsError::InvalidKeepalive(server.name.clone()));
+            }
+
+            // A delay that shrinks, or a jitter outside 0 to 1, makes an empty range for the random jitter, which panics the listener.
+            let reconnect = &server.reconnect;
+            if !reconnect.multiplier.is_finite() || reconnect.multiplier < 1.0 {
+                return Err(SettingsError::InvalidReconnect(format!("multiplier {} of server {} has to be at least 1", reconnect.multiplier, server.name)));
+            }
+            if !(0.0..=1.0).contains(&reconnect.jitter) {
+                return Err(SettingsError::InvalidReconnect(format!("jitter {} of server {} has to be between 0 and 1", reconnect.jitter, server.name)));
+            }
+        }
+
+        // Two connections with the same id would insert every row twice, and share a spool file.
+        for (index, database) in self.databases.iter().enumerate() {
+            if self.databases[..index].iter().any(|other| other.id == database.id) {
+                return Err(SettingsError::DuplicateDatabase(database.id.clone()));
+            }
+
+            // MySQL and PostgreSQL need a server to connect to, a port of 0 is the default one of the driver.
+            if database.driver != Driver::Sqlite && database.host.is_empty() {
+                return Err(SettingsError::InvalidConnection(format!("database {:?} has no host", database.id)));
+            }
+            if !(0..=u16::MAX as i32).contains(&database.port) {
+                return Err(SettingsError::InvalidConnection(format!("{} is not a valid port, for database {:?}", database.port, database.id)));
+            }
+        }
+
+        let mut sink_databases = vec![];
+        for sink in self.sinks() {
+            match sink {
+                SinkSettings::File(file) => {
+                    if file.target_directory.is_empty() {
+                        return Err(SettingsError::EmptyTargetDirectory);
+                    }
+                    log_file::validate_template(log_file::filename_template(&file))?;
+                    file.parse_timezone()?;
+                },
+                SinkSettings::Database(database) => {
+                    if !self.databases.iter().any(|connection| connection.id == database.database) {
+                        return Err(SettingsError::UnknownDatabase(database.database));
+                    }
+                    if sink_databases.contains(&database.database) {
+                        return Err(SettingsError::DuplicateSink(database.database));
+                    }
+                    sink_databases.push(database.database);
+                },
+            }
+        }
+
+        for event_clause in &self.event_clauses {
+            // Otherwise no database would ever pick up its rows.
+            if !self.databases.iter().any(|database| database.id == event_clause.db_connection_id) {
+                return Err(SettingsError::UnknownConnection(event_clause.event_name.clone(), event_clause.db_connection_id.clone()));
+            }
+            event_clause.validate()?;
+        }
+        Ok(())
+    }
+
+    // The sinks the events go to, the declared ones or the log files from basic and every database.
+    pub fn sinks(&self) -> Vec<SinkSettings> {
+        if let Some(sinks) = &self.sinks {
+            return sinks.clone();
+        }
+
+        let mut sinks = vec![SinkSettings::File(Box::new(self.basic.clone()))];
+        for database in &self.databases {
+            sinks.push(SinkSettings::Database(DatabaseSinkSettings {
+                database: database.id.clone(),
+            }));
+        }
+        sinks
+    }
+}
+
+impl FileSettings {
+    fn parse_timezone(&self) -> Result<Tz, SettingsError> {
+        match &self.timezone {
+            Some(name) => name.parse().map_err(|_| SettingsError::InvalidTimezone(format!("{:?} is not a known IANA timezone", name))),
+            None => Ok(Tz::UTC),
+        }
+    }
+
+    // The timezone of the log files, UTC if the settings dont have one.
+    pub fn timezone(&self) -> Tz {
+        self.parse_timezone().expect("the timezone is validated with the settings")
+    }
+}
+
 // If we want to store a event into a database we are going to need 2 things:
 // A database connection.
 // A clause indicating what events we want to store, and how.
@@ -119,25 +405,208 @@ impl Settings {
 // It will contain:
 // - Event name
 // - HashMap containing a link between event data and the database columns.
+//   Repeated headers can be addressed by index, "Output[1]", or by variable name, "ChanVariable(FOO)", see Headers::lookup.
+//   A value can be converted before it is inserted, see DataLink.
+//   Keys like %SERVER_NAME% or %RECEIVED_AT% are filled in by the logger, see placeholder::ReceivedEvent.
 // - Database connection id, and the table name.
-#[derive(Serialize, Deserialize, Debug)]
+// - The SQL types of the columns, used when auto_schema creates them, e.g. { duration = "INTEGER" }.
+//   Others get the type of their conversion, or TEXT.
+// - What happens to a row when a value can not be converted.
+// - An optional where on the headers, only the events matching it get a row, e.g. 'Queue == "sales" && Cause != "16"',
+//   see condition::Condition.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EventClause {
     pub event_name: String,
+    #[serde(default, rename = "where", skip_serializing_if = "Option::is_none")]
+    pub condition: Option<String>,
     pub db_connection_id: String,
     pub db_table: String,
-    pub event_data_link: HashMap<String, String>,
+    #[serde(default)]
+    pub on_conversion_error: ConversionPolicy,
+    pub event_data_link: HashMap<String, DataLink>,
+    #[serde(default)]
+    pub column_types: HashMap<String, String>,
+}
+
+// The column an event value goes to, either just its name, Channel = "channel",
+// or a table with the type the value is converted to, Duration = { column = "duration", type = "int" }.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum DataLink {
+    Column(String),
+    Converted(Conversion),
+}
+
+impl DataLink {
+    pub fn column(&self) -> &str {
+        match self {
+            DataLink::Column(column) => column,
+            DataLink::Converted(conversion) => &conversion.column,
+        }
+    }
+
+    pub fn conversion(&self) -> Option<&Conversion> {
+        match self {
+            DataLink::Column(_) => None,
+            DataLink::Converted(conversion) => Some(conversion),
+        }
+    }
+}
+
+// The format is only used by datetime: a chrono format like "%d/%m/%Y %H:%M:%S" to parse the value with,
+// or "epoch" (the default) for the seconds since 1970 before the first ".", like the start of a Uniqueid.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Conversion {
+    pub column: String,
+    #[serde(rename = "type")]
+    pub value_type: ValueType,
+    pub format: Option<String>,
+}
+
+// - text: the value as it is.
+// - int, float: a number, "12", "-3.5".
+// - bool: yes/no, true/false, on/off or 1/0, in any case.
+// - datetime: a timestamp, see Conversion::format.
+// - duration: whole seconds from "90", "1:30" or "0:01:30".
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueType {
+    #[default]
+    Text,
+    Int,
+    Float,
+    Bool,
+    Datetime,
+    Duration,
+}
+
+// null inserts NULL for the value, skip leaves the row out, error leaves the row out and reports it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ConversionPolicy {
+    #[default]
+    Null,
+    Skip,
+    Error,
+}
+
+impl EventClause {
+    // The table and column names go into the SQL, so they have to be plain identifiers: letters, digits, _ and $,
+    // not starting with a digit. The table can be prefixed by its schema, "schema.table".
+    fn validate(&self) -> Result<(), SettingsError> {
+        let identifier = Regex::new(r"^[A-Za-z_][A-Za-z0-9_$]*$").unwrap();
+
+        let parts: Vec<&str> = self.db_table.split('.').collect();
+        if parts.len() > 2 || !parts.iter().all(|part| identifier.is_match(part)) {
+            return Err(SettingsError::InvalidIdentifier(format!("{:?} is not a valid table name, for event {}", self.db_table, self.event_name)));
+        }
+
+        for link in self.event_data_link.values() {
+            if !identifier.is_match(link.column()) {
+                return Err(SettingsError::InvalidIdentifier(format!("{:?} is not a valid column name, for table {}", link.column(), self.db_table)));
+            }
+
+            if let Some(format) = link.conversion().and_then(|conversion| conversion.format.as_ref()) {
+                if format != "epoch" && StrftimeItems::new(format).any(|item| item == Item::Error) {
+                    return Err(SettingsError::InvalidConversion(format!("{:?} is not a valid datetime format, for column {} of table {}", format, link.column(), self.db_table)));
+                }
+            }
+        }
+
+        if let Some(condition) = &self.condition {
+            if let Err(e) = Condition::parse(condition) {
+                return Err(SettingsError::InvalidCondition(format!("{:?} for event {}: {}", condition, self.event_name, e)));
+            }
+        }
+
+        // A type name with an optional size, like "VARCHAR(64)", "DECIMAL(10, 2)" or "DOUBLE PRECISION".
+        let column_type = Regex::new(r"^[A-Za-z][A-Za-z0-9_ ]*(\(\d+(\s*,\s*\d+)?\))?$").unwrap();
+        for (column, sql_type) in &self.column_types {
+            if !column_type.is_match(sql_type) {
+                return Err(SettingsError::InvalidIdentifier(format!("{:?} is not a valid type, for column {} of table {}", sql_type, column, self.db_table)));
+            }
+        }
+        Ok(())
+    }
 }
 
 // Now we want the ability to store multiple database connections, we will give them a unique string id to identify them.
 // Lets create a struct to hold the database connection information.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DatabaseConnection {
     pub id: String,
+    #[serde(default)]
+    pub driver: Driver,
+    // The server to connect to, sqlite leaves these out.
+    // Without a port the default one of the driver is used, 3306 for MySQL and 5432 for PostgreSQL.
+    #[serde(default)]
     pub host: String,
+    #[serde(default)]
     pub port: i32,
+    #[serde(default)]
     pub user: String,
+    #[serde(default)]
     pub password: String,
-    pub database: String
+    // The database name, or the path of the database file for sqlite.
+    pub database: String,
+    // Creates and migrates the tables of the event clauses, see AutoSchema.
+    // It goes before the tables below, toml can not write a value after them.
+    #[serde(default)]
+    pub auto_schema: AutoSchema,
+    // Replaces the filters for the events written to this database.
+    #[serde(default)]
+    pub filters: Option<Filters>,
+    #[serde(default)]
+    pub batch: Batch,
+    #[serde(default)]
+    pub spool: Spool,
+}
+
+// Whether the tables of the event clauses are created, and their missing columns added, at startup:
+// "off" leaves the tables alone, "apply" runs the CREATE TABLE and ALTER TABLE statements, and "dry_run" only prints them.
+// The tables get an id, a server and a received_at column on top of the event_data_link ones,
+// the server and received_at columns are filled in on every row unless the event_data_link already maps them.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AutoSchema {
+    #[default]
+    Off,
+    Apply,
+    DryRun,
+}
+
+// The kind of database, "mysql", "postgres" or "sqlite".
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Driver {
+    #[default]
+    Mysql,
+    Postgres,
+    Sqlite,
+}
+
+// Rows that could not be written to the database are appended to "<directory>/<id>.spool", and written in order once it
+// is reachable again, trying every retry_interval_ms. While the spool has rows the new ones go after them.
+// Past alert_bytes an alert is logged every time the spool grows by that much more, at max_bytes new rows are dropped.
+// A max_bytes of 0 disables the spool, the rows are dropped right away.
+// Rows the database rejects for what is in them, like a NULL in a NOT NULL column, are never tried again, they go to
+// "<directory>/<id>.quarantine" with the error, even when the spool is disabled.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Spool {
+    pub directory: String,
+    pub max_bytes: u64,
+    pub alert_bytes: u64,
+    pub retry_interval_ms: u64,
+}
+
+// The rows of each event clause are buffered and inserted together, in a transaction, once there are size of them
+// or the oldest one waited interval_ms. A size of 1 inserts every event right away.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Batch {
+    pub size: usize,
+    pub interval_ms: u64,
 }
 
 // Represents a AMI Asterisk Server instance to be monitored.
@@ -148,6 +617,73 @@ pub struct Server {
     pub port: u16,
     pub username: String,
     pub password: String,
+    #[serde(default)]
+    pub auth: AuthType,
+    // Connect with AMI over TLS, usually on port 5039.
+    #[serde(default)]
+    pub tls: bool,
+    // PEM bundle with the CA certificates used to verify the server, on top of the system ones.
+    #[serde(default)]
+    pub ca_file: Option<String>,
+    // PEM certificate and PKCS#8 key presented to servers that require client authentication.
+    #[serde(default)]
+    pub client_cert: Option<String>,
+    #[serde(default)]
+    pub client_key: Option<String>,
+    // Whether the server certificate has to match the host we connect to.
+    #[serde(default = "default_verify_hostname")]
+    pub verify_hostname: bool,
+    // The event classes the server should send us, e.g. "call,agent". Leaving it out keeps the server default.
+    #[serde(default)]
+    pub events: Option<String>,
+    // Regexes sent with the Filter action after login, the server only sends the events matching one of them.
+    // A filter starting with "!" drops the matching events instead, e.g. "!Event: VarSet".
+    #[serde(default)]
+    pub ami_filters: Vec<String>,
+    // Protocol versions we accept from this server, "2" accepts any 2.x, empty accepts everything.
+    #[serde(default)]
+    pub allowed_versions: Vec<AmiVersion>,
+    // Lowest protocol version we accept from this server.
+    #[serde(default)]
+    pub min_version: Option<AmiVersion>,
+    #[serde(default)]
+    pub keepalive: Keepalive,
+    #[serde(default)]
+    pub reconnect: Reconnect,
+    // Replaces the [filters] section for the events of this server.
+    #[serde(default)]
+    pub filters: Option<Filters>,
+}
+
+fn default_verify_hostname() -> bool {
+    true
+}
+
+fn default_csv_columns() -> Vec<String> {
+    vec![String::from("%SERVER_NAME%"), String::from("%TIME%"), String::from("Event")]
+}
+
+// Every interval_ms the listener sends a Ping action, if no reply arrives within timeout_ms the connection is considered dead and we reconnect.
+// timeout_ms is also how long we wait for the connection, the banner and the login responses, so it has to be more than 0.
+// An interval_ms of 0 disables the pings.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Keepalive {
+    pub interval_ms: u64,
+    pub timeout_ms: u64,
+}
+
+// Controls how long a listener waits before trying to connect again after the connection dropped or failed.
+// The first delay is initial_delay_ms, every failed attempt multiplies it by multiplier up to max_delay_ms.
+// On top of that a random jitter between 0 and jitter * delay is added, so servers restarted together dont reconnect in lockstep.
+// The multiplier has to be at least 1 and the jitter between 0 and 1.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Reconnect {
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+    pub multiplier: f64,
+    pub jitter: f64,
 }
 
 
@@ -159,6 +695,69 @@ impl Default for Server {
             port: 5038,
             username: String::from("admin"),
             password: String::from("admin"),
+            auth: AuthType::default(),
+            tls: false,
+            ca_file: None,
+            client_cert: None,
+            client_key: None,
+            verify_hostname: true,
+            events: None,
+            ami_filters: vec![],
+            allowed_versions: vec![],
+            min_version: None,
+            keepalive: Keepalive::default(),
+            reconnect: Reconnect::default(),
+            filters: None,
+        }
+    }
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention {
+            retention_days: 0,
+            max_total_bytes: 0,
+            check_interval_secs: 3600,
+        }
+    }
+}
+
+impl Default for Spool {
+    fn default() -> Self {
+        Spool {
+            directory: String::from("spool"),
+            max_bytes: 100 * 1024 * 1024,
+            alert_bytes: 10 * 1024 * 1024,
+            retry_interval_ms: 5000,
+        }
+    }
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        Batch {
+            size: 100,
+            interval_ms: 1000,
+        }
+    }
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Keepalive {
+            interval_ms: 30000,
+            timeout_ms: 10000,
+        }
+    }
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Reconnect {
+            initial_delay_ms: 1000,
+            max_delay_ms: 60000,
+            multiplier: 2.0,
+            jitter: 0.2,
         }
     }
 }
@@ -166,27 +765,43 @@ impl Default for Server {
 impl Default for Settings {
     fn default() -> Self {
         Settings {
-            basic: Basic::default(),
+            basic: FileSettings::default(),
+            filters: Filters::default(),
             servers: vec![
                 Server::default(),
             ],
             databases: vec![
                 DatabaseConnection::default(),
-                DatabaseConnection::default(),
+                DatabaseConnection {
+                    id: String::from("example_2"),
+                    ..DatabaseConnection::default()
+                },
             ],
             event_clauses: vec![
                 EventClause::default(),
-                EventClause::default(),
+                EventClause {
+                    db_connection_id: String::from("example_2"),
+                    ..EventClause::default()
+                },
             ],
+            sinks: None,
         }
     }
 }
 
-impl Default for Basic {
+impl Default for FileSettings {
     fn default() -> Self {
-        Basic {
+        FileSettings {
             target_directory: String::from("events"),
-            directory_per_server: false
+            directory_per_server: false,
+            timezone: None,
+            iso_timestamp: false,
+            format: Format::default(),
+            csv_columns: default_csv_columns(),
+            compression: Compression::default(),
+            rotation: Rotation::default(),
+            retention: Retention::default(),
+            filters: None,
         }
     }
 }
@@ -195,12 +810,15 @@ impl Default for EventClause {
     fn default() -> Self {
         EventClause {
             event_name: String::from("example"),
+            condition: None,
             event_data_link: [
-                (String::from("example_event_property"), String::from("example_db_column")),
-                (String::from("example_event_property_2"), String::from("example_db_column_2")),
+                (String::from("example_event_property"), DataLink::Column(String::from("example_db_column"))),
+                (String::from("example_event_property_2"), DataLink::Column(String::from("example_db_column_2"))),
             ].iter().cloned().collect(),
             db_connection_id: String::from("example"),
-            db_table: String::from("example")
+            db_table: String::from("example"),
+            column_types: HashMap::new(),
+            on_conversion_error: ConversionPolicy::default(),
         }
     }
 }
@@ -209,11 +827,16 @@ impl Default for DatabaseConnection {
     fn default() -> Self {
         DatabaseConnection {
             id: String::from("example"),
+            driver: Driver::default(),
             host: String::from("example.com"),
             port: 3306,
             user: String::from("example"),
             password: String::from("example"),
-            database: String::from("example")
+            database: String::from("example"),
+            filters: None,
+            batch: Batch::default(),
+            spool: Spool::default(),
+            auto_schema: AutoSchema::default(),
         }
     }
 }
\ No newline at end of file
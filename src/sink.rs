@@ -0,0 +1,115 @@
+use std::{collections::HashMap, error::Error};
+use ami::Packet;
+use chrono::Utc;
+use gethostname::gethostname;
+use uuid::Uuid;
+
+use crate::{database::Pool, database_sink::DatabaseSink, file_sink::FileSink, filter::{EventFilter, FilterSet, sink_filter}, placeholder::ReceivedEvent, settings::{Settings, SinkSettings}};
+
+// An output the events are written to, like the log files or a database.
+// A sink reports its own errors, so one failing sink never stops the events going to the others.
+pub trait Sink {
+    // Takes an event of the server, it already passed the filters of this sink.
+    fn accept(&mut self, received: &ReceivedEvent);
+
+    // Writes out anything the sink is holding on to, called when no events came in for a while.
+    fn flush(&mut self) {}
+
+    // Called once before the logger stops, nothing is accepted after it.
+    fn shutdown(&mut self) {
+        self.flush();
+    }
+}
+
+// A sink with the filter that replaces the server filters for it, if it has one.
+struct Output {
+    sink: Box<dyn Sink>,
+    filter: Option<EventFilter>,
+}
+
+// Hands every event to the sinks whose filters keep it.
+// The event is stamped with its receive time and uuid once, so every sink writes the same ones.
+pub struct Dispatcher {
+    filters: FilterSet,
+    outputs: Vec<Output>,
+    // The hosts of the servers by name, for %SERVER_HOST%.
+    server_hosts: HashMap<String, String>,
+    logger_host: String,
+}
+
+impl Dispatcher {
+    // Creates the sinks from the settings, connecting to the databases they use.
+    // A database we can not connect to is reported and its sinks left out, like it always was.
+    pub fn new(settings: &Settings) -> Result<Self, Box<dyn Error>> {
+        let filters = FilterSet::new(settings)?;
+        let mut pools = HashMap::new();
+        let mut outputs = vec![];
+
+        for sink in settings.sinks() {
+            let output = match sink {
+                SinkSettings::File(file) => Output {
+                    filter: sink_filter(&file.filters)?,
+                    sink: Box::new(FileSink::new(*file, &settings.servers)?),
+                },
+                SinkSettings::Database(database) => {
+                    let connection = settings.databases
+                        .iter()
+                        .find(|connection| connection.id == database.database)
+                        .expect("the database of a sink is validated with the settings");
+
+                    if !pools.contains_key(&connection.id) {
+                        if let Some(pool) = Pool::connect(connection) {
+                            pools.insert(connection.id.clone(), pool);
+                        }
+                    }
+                    let pool = match pools.get(&connection.id) {
+                        Some(pool) => pool.clone(),
+                        None => continue,
+                    };
+
+                    Output {
+                        filter: sink_filter(&connection.filters)?,
+                        sink: Box::new(DatabaseSink::new(connection, pool, &settings.event_clauses)?),
+                    }
+                },
+            };
+            outputs.push(output);
+        }
+
+        Ok(Dispatcher {
+            filters,
+            outputs,
+            server_hosts: settings.servers.iter().map(|server| (server.name.clone(), server.host.clone())).collect(),
+            logger_host: gethostname().to_string_lossy().into_owned(),
+        })
+    }
+
+    pub fn dispatch(&mut self, server_name: &str, event: &Packet) {
+        let received = ReceivedEvent {
+            server_name,
+            server_host: self.server_hosts.get(server_name).map(String::as_str).unwrap_or_default(),
+            logger_host: &self.logger_host,
+            event,
+            received_at: Utc::now(),
+            uuid: Uuid::new_v4(),
+        };
+
+        for output in &mut self.outputs {
+            if self.filters.for_sink(output.filter.as_ref(), server_name).matches(event) {
+                output.sink.accept(&received);
+            }
+        }
+    }
+
+    pub fn flush(&mut self) {
+        for output in &mut self.outputs {
+            output.sink.flush();
+        }
+    }
+
+    pub fn shutdown(&mut self) {
+        for output in &mut self.outputs {
+            output.sink.shutdown();
+        }
+    }
+}
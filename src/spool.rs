@@ -0,0 +1,288 @@
+use std::{fs::{self, File, OpenOptions}, io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write}};
+use serde::{Deserialize, Serialize};
+
+use crate::{database::Value, settings::Spool};
+
+// A row that could not be inserted, one JSON line in the spool.
+#[derive(Serialize, Deserialize)]
+pub struct SpooledRow {
+    pub table: String,
+    pub columns: Vec<String>,
+    pub values: Vec<Value>,
+}
+
+// A row the database rejected for what is in it, one JSON line in "<directory>/<id>.quarantine" with the error.
+#[derive(Serialize)]
+struct QuarantinedRow<'a> {
+    error: &'a str,
+    #[serde(flatten)]
+    row: &'a SpooledRow,
+}
+
+// Sets the rows aside so they can be looked at and fixed by hand, trying them again would only fail again.
+// This happens even when the spool is disabled, the directory of the spool settings is used either way.
+pub fn quarantine(directory: &str, id: &str, rows: &[SpooledRow], error: &str) -> io::Result<()> {
+    fs::create_dir_all(directory)?;
+
+    let mut data = vec![];
+    for row in rows {
+        serde_json::to_writer(&mut data, &QuarantinedRow { error, row })?;
+        data.push(b'\n');
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(format!("{}/{}.quarantine", directory, id))?;
+    file.write_all(&data)?;
+    file.sync_data()
+}
+
+// Where the last complete line of the file ends, a crash in the middle of an append leaves a line without its newline.
+fn complete_length(file: &mut File, size: u64) -> io::Result<u64> {
+    let mut buffer = [0; 4096];
+    let mut end = size;
+    while end > 0 {
+        let start = end.saturating_sub(buffer.len() as u64);
+        let chunk = &mut buffer[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+        if let Some(newline) = chunk.iter().rposition(|&byte| byte == b'\n') {
+            return Ok(start + newline as u64 + 1);
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+// The append-only spool of a database connection.
+// The rows already replayed are tracked by their offset in "<id>.spool.offset", so a restart continues where it stopped.
+// Once every row is replayed the spool is emptied.
+pub struct SpoolFile {
+    id: String,
+    path: String,
+    offset_path: String,
+    settings: Spool,
+    file: File,
+    size: u64,
+    // Where the rows not replayed yet start.
+    offset: u64,
+    // How many times alert_bytes the spool had when we last alerted.
+    alerted: u64,
+}
+
+impl SpoolFile {
+    pub fn open(id: &str, settings: &Spool) -> io::Result<Self> {
+        fs::create_dir_all(&settings.directory)?;
+
+        let path = format!("{}/{}.spool", settings.directory, id);
+        let offset_path = format!("{}.offset", path);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)?;
+
+        // The row cut short never made it to the spool, lets drop it so the next one starts on a line of its own.
+        let mut size = file.metadata()?.len();
+        let complete = complete_length(&mut file, size)?;
+        if complete < size {
+            println!("Dropping incomplete row at the end of the spool of database {}.", id);
+            file.set_len(complete)?;
+            size = complete;
+        }
+        let offset = fs::read_to_string(&offset_path)
+            .ok()
+            .and_then(|offset| offset.trim().parse().ok())
+            .unwrap_or(0)
+            .min(size);
+
+        if offset < size {
+            println!("Spool for database {} has {} bytes to replay.", id, size - offset);
+        }
+
+        Ok(SpoolFile {
+            id: id.to_owned(),
+            path,
+            offset_path,
+            settings: settings.clone(),
+            file,
+            size,
+            offset,
+            alerted: 0,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offset >= self.size
+    }
+
+    // Appends the rows, dropping them if the spool is full.
+    pub fn append(&mut self, rows: &[SpooledRow]) -> io::Result<()> {
+        if self.size >= self.settings.max_bytes {
+            println!("Alert: the spool of database {} is full ({} bytes), dropping {} rows.", self.id, self.size, rows.len());
+            return Ok(());
+        }
+
+        let mut data = vec![];
+        for row in rows {
+            serde_json::to_writer(&mut data, row)?;
+            data.push(b'\n');
+        }
+        self.file.write_all(&data)?;
+        self.file.sync_data()?;
+        self.size += data.len() as u64;
+
+        println!("Spooled {} rows for database {} ({} bytes in the spool).", rows.len(), self.id, self.size);
+
+        if self.settings.alert_bytes > 0 && self.size / self.settings.alert_bytes > self.alerted {
+            self.alerted = self.size / self.settings.alert_bytes;
+            println!("Alert: the spool of database {} has grown to {} bytes.", self.id, self.size);
+        }
+        Ok(())
+    }
+
+    // Reads up to max rows that were not replayed yet, each with the offset right after it, and the offset right after
+    // the last line read, which can be past the last row when the lines after it could not be read.
+    pub fn read(&self, max: usize) -> io::Result<(Vec<(SpooledRow, u64)>, u64)> {
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut reader = BufReader::new(file);
+
+        let mut rows = vec![];
+        let mut offset = self.offset;
+        let mut line = String::new();
+        while rows.len() < max {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 {
+                break;
+            }
+            offset += read as u64;
+
+            // A line without its newline was cut short by a crash, it never made it to the spool.
+            if !line.ends_with('\n') {
+                println!("Skipping incomplete row at the end of the spool of database {}.", self.id);
+                break;
+            }
+
+            match serde_json::from_str(&line) {
+                Ok(row) => rows.push((row, offset)),
+                Err(e) => println!("Skipping unreadable row in the spool of database {} with error: {}", self.id, e),
+            }
+        }
+
+        Ok((rows, offset))
+    }
+
+    // Marks the rows up to the offset as replayed, emptying the spool when there are none left.
+    pub fn advance(&mut self, offset: u64) -> io::Result<()> {
+        self.offset = offset;
+
+        if self.offset >= self.size {
+            self.file.set_len(0)?;
+            self.size = 0;
+            self.offset = 0;
+            self.alerted = 0;
+            return match fs::remove_file(&self.offset_path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+
+        fs::write(&self.offset_path, self.offset.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, path::{Path, PathBuf}, process};
+
+    use super::*;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("sms-spool-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn settings(directory: &Path) -> Spool {
+        Spool {
+            directory: directory.to_string_lossy().into_owned(),
+            ..Spool::default()
+        }
+    }
+
+    fn row(n: i64) -> SpooledRow {
+        SpooledRow {
+            table: String::from("calls"),
+            columns: vec![String::from("n")],
+            values: vec![Value::Int(n)],
+        }
+    }
+
+    fn values(rows: &[(SpooledRow, u64)]) -> Vec<Value> {
+        rows.iter().flat_map(|(row, _)| row.values.clone()).collect()
+    }
+
+    #[test]
+    fn replays_in_order_and_empties() {
+        let directory = directory("replay");
+        let mut spool = SpoolFile::open("db", &settings(&directory)).unwrap();
+        assert!(spool.is_empty());
+        spool.append(&[row(1), row(2), row(3)]).unwrap();
+        assert!(!spool.is_empty());
+
+        let (rows, offset) = spool.read(2).unwrap();
+        assert_eq!(values(&rows), vec![Value::Int(1), Value::Int(2)]);
+        assert_eq!(rows[1].1, offset);
+        assert!(rows[0].1 < rows[1].1);
+
+        // Only the first row is done, the second comes back on the next read.
+        spool.advance(rows[0].1).unwrap();
+        let (rows, offset) = spool.read(10).unwrap();
+        assert_eq!(values(&rows), vec![Value::Int(2), Value::Int(3)]);
+
+        spool.advance(offset).unwrap();
+        assert!(spool.is_empty());
+        assert_eq!(fs::metadata(directory.join("db.spool")).unwrap().len(), 0);
+        assert!(!directory.join("db.spool.offset").exists());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn reopens_at_the_offset() {
+        let directory = directory("offset");
+        let mut spool = SpoolFile::open("db", &settings(&directory)).unwrap();
+        spool.append(&[row(1), row(2), row(3)]).unwrap();
+        let (rows, _) = spool.read(1).unwrap();
+        spool.advance(rows[0].1).unwrap();
+        drop(spool);
+
+        assert!(directory.join("db.spool.offset").exists());
+        let spool = SpoolFile::open("db", &settings(&directory)).unwrap();
+        let (rows, _) = spool.read(10).unwrap();
+        assert_eq!(values(&rows), vec![Value::Int(2), Value::Int(3)]);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn drops_a_row_cut_short_on_reopen() {
+        let directory = directory("cut");
+        let mut spool = SpoolFile::open("db", &settings(&directory)).unwrap();
+        spool.append(&[row(1), row(2)]).unwrap();
+        drop(spool);
+
+        // A crash in the middle of the next append.
+        let mut file = OpenOptions::new().append(true).open(directory.join("db.spool")).unwrap();
+        file.write_all(b"{\"table\":\"calls\",\"colu").unwrap();
+        drop(file);
+
+        let mut spool = SpoolFile::open("db", &settings(&directory)).unwrap();
+        spool.append(&[row(3)]).unwrap();
+        let (rows, offset) = spool.read(10).unwrap();
+        assert_eq!(values(&rows), vec![Value::Int(1), Value::Int(2), Value::Int(3)]);
+        assert_eq!(offset, fs::metadata(directory.join("db.spool")).unwrap().len());
+        fs::remove_dir_all(directory).unwrap();
+    }
+}